@@ -1,4 +1,7 @@
 fn main() {
-    println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
-    println!("cargo::rustc-link-arg=-Trom_functions.x");
+    // The linker scripts only exist for the chip, host builds of the library do without them
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("riscv32") {
+        println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
+        println!("cargo::rustc-link-arg=-Trom_functions.x");
+    }
 }
@@ -0,0 +1,174 @@
+use crate::duty::Duty;
+use crate::filter::{Filter, FilterConfig};
+use crate::map_range::{map_range_clamped, Rounding};
+use crate::potentiometer::AdcSource;
+
+/// Divider that feeds the supply voltage to the battery ADC pin, and how a sagging supply limits
+/// the motor
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryConfig {
+    pub sample_count: u16, // Number of samples to average
+    pub filter: FilterConfig,
+    pub divider_top: u32,    // Ω from the supply to the ADC pin
+    pub divider_bottom: u32, // Ω from the ADC pin to ground
+    pub min_plausible: u16,  // mV, lower readings mean no divider is fitted
+    pub derate_start: u16,   // mV below which the max duty is reduced
+    pub derate_end: u16,     // mV at which the max duty is down to the min duty
+    pub cutoff_readings: u8, // Consecutive readings below the cutoff before giving up
+}
+
+impl BatteryConfig {
+    /// Supply voltage in mV for a voltage at the ADC pin
+    pub fn supply_voltage(&self, pin_voltage: u16) -> u16 {
+        (pin_voltage as u64 * (self.divider_top as u64 + self.divider_bottom as u64))
+            .checked_div(self.divider_bottom.into())
+            .map_or(0, |voltage| voltage.min(u16::MAX.into()) as u16)
+    }
+
+    /// Lowers `max_duty` towards `min_duty` as the supply voltage drops through the derating range
+    pub fn derate(&self, supply_voltage: u16, min_duty: Duty, max_duty: Duty) -> Duty {
+        map_range_clamped(
+            supply_voltage,
+            self.derate_end,
+            self.derate_start,
+            min_duty.raw(),
+            max_duty.raw(),
+            Rounding::Floor,
+        )
+        .map_or(max_duty, Duty::from_raw)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryReading {
+    pub pin_voltage: u16,            // mV, filtered
+    pub supply_voltage: Option<u16>, // mV, `None` without a divider
+    pub below_cutoff: bool,
+}
+
+/// Turns raw samples of the battery ADC pin into the supply voltage
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    filter: Filter,
+    low_readings: u8,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            filter: Filter::new(config.filter, 0, u16::MAX),
+            low_readings: 0,
+        }
+    }
+
+    /// Filters a batch of samples, counting how long the supply has stayed below `cutoff` mV so a
+    /// single dip under load does not end the session
+    pub async fn update<S: AdcSource>(&mut self, source: &mut S, cutoff: u16) -> BatteryReading {
+        let raw_voltage = self.filter.average(source, self.config.sample_count).await;
+        let pin_voltage = self.filter.apply(raw_voltage);
+        let supply_voltage = Some(self.config.supply_voltage(pin_voltage))
+            .filter(|&voltage| voltage >= self.config.min_plausible);
+        self.low_readings = match supply_voltage {
+            Some(voltage) if voltage < cutoff => self.low_readings.saturating_add(1),
+            _ => 0,
+        };
+
+        BatteryReading {
+            pin_voltage,
+            supply_voltage,
+            below_cutoff: self.low_readings >= self.config.cutoff_readings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_adc::FakeAdc;
+    use embassy_futures::block_on;
+
+    const CONFIG: BatteryConfig = BatteryConfig {
+        sample_count: 4,
+        filter: FilterConfig {
+            median_window: 1,
+            ema_alpha_permille: 1000,
+            deadband: 0,
+        },
+        divider_top: 100_000,
+        divider_bottom: 47_000,
+        min_plausible: 2_500,
+        derate_start: 3_600,
+        derate_end: 3_200,
+        cutoff_readings: 3,
+    };
+    const CUTOFF: u16 = 3_300; // mV
+
+    /// Pin voltage the divider produces for a supply voltage
+    fn pin_voltage(supply_voltage: u16) -> u16 {
+        (supply_voltage as u32 * CONFIG.divider_bottom
+            / (CONFIG.divider_top + CONFIG.divider_bottom)) as u16
+    }
+
+    #[test]
+    fn supply_voltage_undoes_the_divider() {
+        assert_eq!(CONFIG.supply_voltage(0), 0);
+        assert_eq!(CONFIG.supply_voltage(1_000), 3_127);
+        assert_eq!(CONFIG.supply_voltage(u16::MAX), u16::MAX);
+        let no_divider = BatteryConfig {
+            divider_bottom: 0,
+            ..CONFIG
+        };
+        assert_eq!(no_divider.supply_voltage(1_000), 0);
+    }
+
+    #[test]
+    fn derate_lowers_the_max_duty_through_the_derating_range() {
+        let min_duty = Duty::from_raw(4_000);
+        let max_duty = Duty::from_raw(12_000);
+        assert_eq!(CONFIG.derate(4_200, min_duty, max_duty), max_duty);
+        assert_eq!(CONFIG.derate(3_600, min_duty, max_duty), max_duty);
+        assert_eq!(
+            CONFIG.derate(3_400, min_duty, max_duty),
+            Duty::from_raw(8_000)
+        );
+        assert_eq!(CONFIG.derate(3_200, min_duty, max_duty), min_duty);
+        assert_eq!(CONFIG.derate(2_900, min_duty, max_duty), min_duty);
+    }
+
+    #[test]
+    fn cutoff_needs_consecutive_low_readings() {
+        let mut monitor = BatteryMonitor::new(CONFIG);
+        let mut adc = FakeAdc::new(vec![pin_voltage(3_250)]);
+        let mut below_cutoff = || block_on(monitor.update(&mut adc, CUTOFF)).below_cutoff;
+        assert!(!below_cutoff());
+        assert!(!below_cutoff());
+        assert!(below_cutoff());
+        assert!(below_cutoff());
+    }
+
+    #[test]
+    fn a_single_dip_does_not_trip_the_cutoff() {
+        let mut monitor = BatteryMonitor::new(CONFIG);
+        let mut adc = FakeAdc::new(vec![pin_voltage(3_250)]);
+        block_on(monitor.update(&mut adc, CUTOFF));
+        block_on(monitor.update(&mut adc, CUTOFF));
+        adc.set_samples(vec![pin_voltage(3_800)]);
+        assert!(!block_on(monitor.update(&mut adc, CUTOFF)).below_cutoff);
+        adc.set_samples(vec![pin_voltage(3_250)]);
+        for _ in 0..2 {
+            assert!(!block_on(monitor.update(&mut adc, CUTOFF)).below_cutoff);
+        }
+    }
+
+    #[test]
+    fn floating_pin_is_not_a_flat_cell() {
+        let mut monitor = BatteryMonitor::new(CONFIG);
+        let mut adc = FakeAdc::new(vec![50]);
+        for _ in 0..10 {
+            let reading = block_on(monitor.update(&mut adc, CUTOFF));
+            assert_eq!(reading.supply_voltage, None);
+            assert!(!reading.below_cutoff);
+        }
+    }
+}
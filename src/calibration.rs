@@ -0,0 +1,211 @@
+use crate::flash_record::{self, MAGIC_LEN};
+use core::fmt;
+use serde::Serialize;
+
+const MAGIC: [u8; MAGIC_LEN] = *b"CAL2";
+pub const MAX_KNOBS: usize = 8;
+pub const CALIBRATION_LEN: usize = flash_record::record_len(1 + MAX_KNOBS * 4); // Count, ranges
+const CALIBRATION_MARGIN_PERMILLE: u32 = 20; // Pulls the recorded ends inwards so they stay reachable
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VoltageRange {
+    pub min: u16, // mV
+    pub max: u16, // mV
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    KnobNotSwept(usize),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::KnobNotSwept(knob) => {
+                write!(f, "knob {} was not swept across its range", knob)
+            }
+        }
+    }
+}
+
+/// Voltage range each knob actually covers between its end stops, indexed by the knob's position
+/// in the firmware's knob table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    ranges: [Option<VoltageRange>; MAX_KNOBS],
+}
+
+impl Calibration {
+    pub const NONE: Self = Self {
+        ranges: [None; MAX_KNOBS],
+    };
+
+    /// `None` for a knob that has not been calibrated yet, e.g. one added after the last calibration
+    pub fn range(&self, knob: usize) -> Option<VoltageRange> {
+        self.ranges.get(knob).copied().flatten()
+    }
+
+    pub fn to_bytes(self) -> [u8; CALIBRATION_LEN] {
+        flash_record::encode(MAGIC, |payload| {
+            payload[0] = self
+                .ranges
+                .iter()
+                .take_while(|range| range.is_some())
+                .count() as u8;
+            let chunks = payload[1..].chunks_exact_mut(4);
+            for (range, chunk) in self.ranges.iter().map_while(|range| *range).zip(chunks) {
+                chunk[..2].copy_from_slice(&range.min.to_le_bytes());
+                chunk[2..].copy_from_slice(&range.max.to_le_bytes());
+            }
+        })
+    }
+
+    /// `None` for erased flash, data written by another firmware or a corrupted calibration
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let payload = flash_record::decode(MAGIC, bytes, CALIBRATION_LEN)?;
+        let count = payload[0] as usize;
+        if count > MAX_KNOBS {
+            return None;
+        }
+
+        let mut calibration = Self::NONE;
+        let chunks = payload[1..].chunks_exact(4);
+        for (range, chunk) in calibration.ranges[..count].iter_mut().zip(chunks) {
+            let min = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let max = u16::from_le_bytes([chunk[2], chunk[3]]);
+            if min >= max {
+                return None;
+            }
+            *range = Some(VoltageRange { min, max });
+        }
+        Some(calibration)
+    }
+}
+
+/// Tracks the lowest and highest voltage seen on each knob while the user sweeps them
+#[derive(Debug)]
+pub struct CalibrationRecorder {
+    knob_count: usize,
+    observed: [Option<VoltageRange>; MAX_KNOBS],
+}
+
+impl CalibrationRecorder {
+    /// Expects the knobs numbered `0..knob_count`, at most `MAX_KNOBS` of them
+    pub fn new(knob_count: usize) -> Self {
+        Self {
+            knob_count: knob_count.min(MAX_KNOBS),
+            observed: [None; MAX_KNOBS],
+        }
+    }
+
+    pub fn record(&mut self, knob: usize, voltage: u16) {
+        let Some(observed) = self.observed[..self.knob_count].get_mut(knob) else {
+            return;
+        };
+        let observed = observed.get_or_insert(VoltageRange {
+            min: voltage,
+            max: voltage,
+        });
+        observed.min = observed.min.min(voltage);
+        observed.max = observed.max.max(voltage);
+    }
+
+    /// Every knob has to have been swept across at least `min_span` mV
+    pub fn finish(self, min_span: u16) -> Result<Calibration, CalibrationError> {
+        let mut calibration = Calibration::NONE;
+        for knob in 0..self.knob_count {
+            let observed = self.observed[knob]
+                .filter(|observed| observed.max - observed.min >= min_span.max(1))
+                .ok_or(CalibrationError::KnobNotSwept(knob))?;
+            let margin =
+                ((observed.max - observed.min) as u32 * CALIBRATION_MARGIN_PERMILLE / 1000) as u16;
+            calibration.ranges[knob] = Some(VoltageRange {
+                min: observed.min + margin,
+                max: observed.max - margin,
+            });
+        }
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(recorder: &mut CalibrationRecorder, knob: usize, min: u16, max: u16) {
+        recorder.record(knob, (min + max) / 2);
+        recorder.record(knob, min);
+        recorder.record(knob, max);
+    }
+
+    #[test]
+    fn recorder_pulls_the_swept_ends_inwards() {
+        let mut recorder = CalibrationRecorder::new(2);
+        sweep(&mut recorder, 0, 100, 3100);
+        sweep(&mut recorder, 1, 500, 1500);
+        let calibration = recorder.finish(500).unwrap();
+        assert_eq!(
+            calibration.range(0),
+            Some(VoltageRange {
+                min: 160,
+                max: 3040
+            })
+        );
+        assert_eq!(
+            calibration.range(1),
+            Some(VoltageRange {
+                min: 520,
+                max: 1480
+            })
+        );
+        assert_eq!(calibration.range(2), None);
+    }
+
+    #[test]
+    fn every_knob_has_to_be_swept() {
+        let mut recorder = CalibrationRecorder::new(2);
+        sweep(&mut recorder, 0, 100, 3100);
+        sweep(&mut recorder, 1, 1000, 1200);
+        assert_eq!(recorder.finish(500), Err(CalibrationError::KnobNotSwept(1)));
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut recorder = CalibrationRecorder::new(3);
+        for knob in 0..3 {
+            sweep(
+                &mut recorder,
+                knob,
+                100 * knob as u16,
+                2000 + 100 * knob as u16,
+            );
+        }
+        let calibration = recorder.finish(500).unwrap();
+        assert_eq!(
+            Calibration::from_bytes(&calibration.to_bytes()),
+            Some(calibration)
+        );
+    }
+
+    #[test]
+    fn knobs_added_since_the_last_calibration_stay_uncalibrated() {
+        let mut recorder = CalibrationRecorder::new(1);
+        sweep(&mut recorder, 0, 100, 3100);
+        recorder.record(1, 0);
+        let calibration =
+            Calibration::from_bytes(&recorder.finish(500).unwrap().to_bytes()).unwrap();
+        assert!(calibration.range(0).is_some());
+        assert_eq!(calibration.range(1), None);
+    }
+
+    #[test]
+    fn rejects_erased_or_corrupted_flash() {
+        assert_eq!(Calibration::from_bytes(&[0xFF; CALIBRATION_LEN]), None);
+        let mut recorder = CalibrationRecorder::new(1);
+        sweep(&mut recorder, 0, 100, 3100);
+        let mut bytes = recorder.finish(500).unwrap().to_bytes();
+        bytes[MAGIC_LEN + 1] ^= 1;
+        assert_eq!(Calibration::from_bytes(&bytes), None);
+        assert_eq!(Calibration::from_bytes(&bytes[..CALIBRATION_LEN - 1]), None);
+    }
+}
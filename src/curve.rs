@@ -0,0 +1,203 @@
+use crate::map_range::{map_range_clamped, Rounding};
+
+pub const CURVE_SCALE: u32 = 1000; // Curve input and output are expressed in permille
+const CURVE_DOUBLINGS: u32 = 4; // Steepness of the logarithmic and exponential curves
+
+/// Knob positions stretched over the slow half of the output before catching up
+const SOFT_START_TABLE: [(u16, u16); 4] = [(0, 0), (500, 200), (800, 450), (1000, 1000)];
+
+/// Shape of a knob's response between its two end stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Coarse near the start of the travel, fine near the end
+    Logarithmic,
+    /// Fine near the start of the travel, coarse near the end
+    Exponential,
+    /// Piecewise-linear through `(input, output)` points in permille, sorted by input, with
+    /// non-decreasing outputs
+    Lookup(&'static [(u16, u16)]),
+}
+
+impl Curve {
+    pub const SOFT_START: Curve = Curve::Lookup(&SOFT_START_TABLE);
+    /// Every curve that can be selected by name
+    pub const ALL_NAMED: [Curve; 4] = [
+        Curve::Linear,
+        Curve::Logarithmic,
+        Curve::Exponential,
+        Curve::SOFT_START,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Curve::Linear => "linear",
+            Curve::Logarithmic => "logarithmic",
+            Curve::Exponential => "exponential",
+            Curve::Lookup(table) if table == SOFT_START_TABLE => "soft_start",
+            Curve::Lookup(_) => "lookup",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL_NAMED
+            .into_iter()
+            .find(|curve| curve.name() == name)
+    }
+
+    /// Maps a position in permille of the knob's travel onto permille of the output range.
+    /// Every curve passes through (0, 0) and (1000, 1000) and never decreases in between
+    pub fn apply(self, position: u32) -> u32 {
+        let position = position.min(CURVE_SCALE);
+        match self {
+            Curve::Linear => position,
+            Curve::Logarithmic => logarithmic(position, CURVE_DOUBLINGS),
+            Curve::Exponential => exponential(position, CURVE_DOUBLINGS),
+            Curve::Lookup(table) => lookup(table, position),
+        }
+    }
+}
+
+/// (2^(kt) - 1) / (2^k - 1), with 2^x linearly interpolated between whole powers
+pub fn exponential(position: u32, doublings: u32) -> u32 {
+    let scale = CURVE_SCALE as u64;
+    let exponent = doublings as u64 * position.min(CURVE_SCALE) as u64;
+    let whole = exponent / scale;
+    let fraction = exponent % scale;
+    let power = (1 << whole) * (scale + fraction);
+    ((power - scale) / ((1 << doublings) - 1)) as u32
+}
+
+/// Exact inverse of `exponential`: log2(1 + t(2^k - 1)) / k
+pub fn logarithmic(position: u32, doublings: u32) -> u32 {
+    let scale = CURVE_SCALE as u64;
+    let power = scale + position.min(CURVE_SCALE) as u64 * ((1 << doublings) - 1);
+    let whole = (power / scale).ilog2() as u64;
+    let fraction = (power - (scale << whole)) / (1 << whole);
+    ((whole * scale + fraction) / doublings as u64) as u32
+}
+
+fn lookup(table: &[(u16, u16)], position: u32) -> u32 {
+    let output_permille = |output: u16| (output as u32).min(CURVE_SCALE);
+    let Some(&(first_input, first_output)) = table.first() else {
+        return position;
+    };
+    if position <= first_input as u32 {
+        return output_permille(first_output);
+    }
+    for segment in table.windows(2) {
+        let ((start_input, start_output), (end_input, end_output)) = (segment[0], segment[1]);
+        let (start_input, end_input) = (start_input as u32, end_input as u32);
+        if position <= end_input {
+            let (start_output, end_output) =
+                (output_permille(start_output), output_permille(end_output));
+            // Equal inputs step straight to the end of the segment
+            return map_range_clamped(
+                position,
+                start_input,
+                end_input,
+                start_output,
+                end_output.max(start_output),
+                Rounding::Floor,
+            )
+            .unwrap_or(end_output);
+        }
+    }
+    table
+        .last()
+        .map_or(position, |&(_, output)| output_permille(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [Curve; 5] = [
+        Curve::Linear,
+        Curve::Logarithmic,
+        Curve::Exponential,
+        Curve::SOFT_START,
+        Curve::Lookup(&[(0, 100), (300, 300), (300, 600), (1000, 900)]),
+    ];
+
+    #[test]
+    fn every_curve_is_monotonic() {
+        for curve in CURVES {
+            let outputs: Vec<u32> = (0..=CURVE_SCALE)
+                .map(|position| curve.apply(position))
+                .collect();
+            assert!(
+                outputs.windows(2).all(|pair| pair[0] <= pair[1]),
+                "{} curve decreases",
+                curve.name()
+            );
+            assert!(outputs.iter().all(|&output| output <= CURVE_SCALE));
+        }
+    }
+
+    #[test]
+    fn named_curves_pass_through_both_end_points() {
+        for curve in Curve::ALL_NAMED {
+            assert_eq!(curve.apply(0), 0, "{} curve start", curve.name());
+            assert_eq!(
+                curve.apply(CURVE_SCALE),
+                CURVE_SCALE,
+                "{} curve end",
+                curve.name()
+            );
+            assert_eq!(
+                curve.apply(2 * CURVE_SCALE),
+                CURVE_SCALE,
+                "{} curve past the end",
+                curve.name()
+            );
+        }
+    }
+
+    #[test]
+    fn exponential_and_logarithmic_bend_opposite_ways() {
+        let midpoint = CURVE_SCALE / 2;
+        assert!(Curve::Exponential.apply(midpoint) < midpoint);
+        assert!(Curve::Logarithmic.apply(midpoint) > midpoint);
+        for position in (0..=CURVE_SCALE).step_by(50) {
+            let round_trip = exponential(logarithmic(position, CURVE_DOUBLINGS), CURVE_DOUBLINGS);
+            assert!(
+                round_trip.abs_diff(position) <= 2,
+                "{} -> {}",
+                position,
+                round_trip
+            );
+        }
+    }
+
+    #[test]
+    fn other_doublings_keep_the_end_points() {
+        for doublings in 1..=8 {
+            assert_eq!(exponential(0, doublings), 0);
+            assert_eq!(exponential(CURVE_SCALE, doublings), CURVE_SCALE);
+            assert_eq!(logarithmic(0, doublings), 0);
+            assert_eq!(logarithmic(CURVE_SCALE, doublings), CURVE_SCALE);
+        }
+    }
+
+    #[test]
+    fn lookup_interpolates_between_points_and_holds_past_them() {
+        let curve = CURVES[4];
+        assert_eq!(curve.apply(0), 100);
+        assert_eq!(curve.apply(150), 200);
+        assert_eq!(curve.apply(300), 300);
+        assert_eq!(curve.apply(301), 600);
+        assert_eq!(curve.apply(1000), 900);
+        assert_eq!(Curve::SOFT_START.apply(250), 100);
+        assert_eq!(Curve::Lookup(&[]).apply(400), 400);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for curve in Curve::ALL_NAMED {
+            assert_eq!(Curve::from_name(curve.name()), Some(curve));
+        }
+        assert_eq!(Curve::from_name("lookup"), None);
+    }
+}
@@ -0,0 +1,61 @@
+use core::fmt;
+use serde::Serialize;
+
+pub const DUTY_RESOLUTION_BITS: u32 = 14; // Must match the resolution the PWM timer is configured with
+
+/// PWM duty cycle in raw LEDC units, where `Duty::MAX` (2^14) keeps the output permanently high
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize)]
+pub struct Duty(u16);
+
+impl Duty {
+    pub const MAX: Duty = Duty(1 << DUTY_RESOLUTION_BITS);
+
+    /// Values above `Duty::MAX` are saturated
+    pub const fn from_raw(raw: u16) -> Self {
+        if raw > Self::MAX.0 {
+            Self::MAX
+        } else {
+            Duty(raw)
+        }
+    }
+
+    pub const fn from_percent(percent: u8) -> Self {
+        Self::from_permille(percent as u16 * 10)
+    }
+
+    pub const fn from_permille(permille: u16) -> Self {
+        Self::from_raw((permille as u32 * Self::MAX.0 as u32 / 1000) as u16)
+    }
+
+    pub const fn raw(self) -> u16 {
+        self.0
+    }
+
+    pub const fn permille(self) -> u16 {
+        (self.0 as u32 * 1000 / Self::MAX.0 as u32) as u16
+    }
+
+    /// Rounded to the nearest percent, so `from_percent(p).percent() == p`
+    pub const fn percent(self) -> u8 {
+        ((self.0 as u32 * 100 + Self::MAX.0 as u32 / 2) / Self::MAX.0 as u32) as u8
+    }
+}
+
+impl fmt::Display for Duty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permille = self.permille();
+        write!(f, "{}.{}%", permille / 10, permille % 10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_round_trips() {
+        for percent in 0..=100 {
+            assert_eq!(Duty::from_percent(percent).percent(), percent);
+        }
+    }
+}
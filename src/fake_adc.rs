@@ -0,0 +1,37 @@
+use crate::potentiometer::AdcSource;
+use alloc::vec::Vec;
+
+/// ADC source for host tests that plays back a fixed sequence of samples, repeating it as needed
+#[derive(Debug, Default)]
+pub struct FakeAdc {
+    samples: Vec<u16>,
+    position: usize,
+    reads: usize,
+}
+
+impl FakeAdc {
+    pub fn new(samples: Vec<u16>) -> Self {
+        Self {
+            samples,
+            ..Self::default()
+        }
+    }
+
+    pub fn set_samples(&mut self, samples: Vec<u16>) {
+        self.samples = samples;
+        self.position = 0;
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+}
+
+impl AdcSource for FakeAdc {
+    async fn read_mv(&mut self) -> u16 {
+        let sample = self.samples.get(self.position).copied().unwrap_or(0);
+        self.position = (self.position + 1) % self.samples.len().max(1);
+        self.reads += 1;
+        sample
+    }
+}
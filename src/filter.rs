@@ -0,0 +1,239 @@
+use crate::potentiometer::AdcSource;
+
+const MAX_MEDIAN_WINDOW: usize = 15;
+const EMA_SCALE: u32 = 1000; // The moving average is kept in µV to avoid rounding drift
+
+/// Stages applied to raw potentiometer samples before they are mapped. Each stage can be disabled
+/// on its own: a median window of 1, an EMA weight of 1000 permille or a deadband of 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterConfig {
+    pub median_window: u8, // Samples per median, rejects single-sample outliers
+    pub ema_alpha_permille: u16, // Weight of a new batch in the exponential moving average
+    pub deadband: u16,     // mV the filtered voltage must move before the output follows
+}
+
+pub struct Filter {
+    config: FilterConfig,
+    min_voltage: u16, // mV
+    max_voltage: u16, // mV
+    ema: Option<u32>,
+    held_voltage: Option<u16>,
+}
+
+impl Filter {
+    pub fn new(config: FilterConfig, min_voltage: u16, max_voltage: u16) -> Self {
+        Self {
+            config,
+            min_voltage,
+            max_voltage: max_voltage.max(min_voltage),
+            ema: None,
+            held_voltage: None,
+        }
+    }
+
+    /// Narrows or widens the range voltages are clamped to, e.g. after a calibration
+    pub fn set_voltage_range(&mut self, min_voltage: u16, max_voltage: u16) {
+        self.min_voltage = min_voltage;
+        self.max_voltage = max_voltage.max(min_voltage);
+    }
+
+    /// Reads a batch of samples and returns their outlier-free average in mV, before any smoothing
+    pub async fn average<S: AdcSource>(&self, source: &mut S, sample_count: u16) -> u16 {
+        median_average(source, sample_count.max(1), self.config.median_window).await
+    }
+
+    /// Runs an averaged batch voltage through the moving average and the deadband
+    pub fn apply(&mut self, voltage: u16) -> u16 {
+        let voltage = voltage.clamp(self.min_voltage, self.max_voltage);
+
+        let alpha = self.config.ema_alpha_permille.min(1000) as u32;
+        let target = voltage as u32 * EMA_SCALE;
+        let ema = match self.ema {
+            Some(ema) if target >= ema => ema + (target - ema) * alpha / 1000,
+            Some(ema) => ema - (ema - target) * alpha / 1000,
+            None => target,
+        };
+        self.ema = Some(ema);
+        let smoothed = ((ema + EMA_SCALE / 2) / EMA_SCALE) as u16;
+
+        // Hold the output until the knob clearly moved, but always let the end stops through
+        let held = match self.held_voltage {
+            Some(held)
+                if held.abs_diff(smoothed) <= self.config.deadband
+                    && smoothed != self.min_voltage
+                    && smoothed != self.max_voltage =>
+            {
+                held
+            }
+            _ => smoothed,
+        };
+        self.held_voltage = Some(held);
+        held
+    }
+}
+
+/// Mean of the medians of consecutive groups of samples
+async fn median_average<S: AdcSource>(source: &mut S, sample_count: u16, median_window: u8) -> u16 {
+    let window = (median_window as usize).clamp(1, MAX_MEDIAN_WINDOW);
+    let mut group = [0u16; MAX_MEDIAN_WINDOW];
+    let mut remaining = sample_count as usize;
+    let mut median_sum: u32 = 0;
+    let mut median_count: u32 = 0;
+    while remaining > 0 {
+        let group_len = window.min(remaining);
+        let group = &mut group[..group_len];
+        for sample in group.iter_mut() {
+            *sample = source.read_mv().await;
+        }
+        group.sort_unstable();
+        median_sum += group[group_len / 2] as u32;
+        median_count += 1;
+        remaining -= group_len;
+    }
+    (median_sum / median_count) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_adc::FakeAdc;
+    use embassy_futures::block_on;
+
+    const UNFILTERED: FilterConfig = FilterConfig {
+        median_window: 1,
+        ema_alpha_permille: 1000,
+        deadband: 0,
+    };
+
+    #[test]
+    fn average_reads_the_requested_number_of_samples() {
+        let filter = Filter::new(UNFILTERED, 0, 3000);
+        let mut adc = FakeAdc::new(vec![1000, 2000]);
+        assert_eq!(block_on(filter.average(&mut adc, 10)), 1500);
+        assert_eq!(adc.reads(), 10);
+
+        adc.set_samples(vec![500]);
+        assert_eq!(block_on(filter.average(&mut adc, 3)), 500);
+        assert_eq!(adc.reads(), 13);
+    }
+
+    #[test]
+    fn median_rejects_single_sample_spikes() {
+        let filter = Filter::new(
+            FilterConfig {
+                median_window: 5,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        let mut adc = FakeAdc::new(vec![1000, 1000, 3300, 1000, 1000]);
+        assert_eq!(block_on(filter.average(&mut adc, 10)), 1000);
+
+        let unfiltered = Filter::new(UNFILTERED, 0, 3000);
+        assert_eq!(block_on(unfiltered.average(&mut adc, 10)), 1460);
+    }
+
+    #[test]
+    fn median_of_a_short_last_group_still_counts() {
+        let filter = Filter::new(
+            FilterConfig {
+                median_window: 5,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        let mut adc = FakeAdc::new(vec![1000, 1000, 1000, 1000, 1000, 2000, 2000]);
+        assert_eq!(block_on(filter.average(&mut adc, 7)), 1500);
+    }
+
+    #[test]
+    fn moving_average_follows_a_step_without_overshooting() {
+        let mut filter = Filter::new(
+            FilterConfig {
+                ema_alpha_permille: 500,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        assert_eq!(filter.apply(1000), 1000);
+        let outputs: Vec<u16> = (0..30).map(|_| filter.apply(2000)).collect();
+        assert_eq!(&outputs[..3], [1500, 1750, 1875]);
+        assert!(outputs.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(outputs.last(), Some(&2000));
+    }
+
+    #[test]
+    fn moving_average_damps_noise() {
+        let mut filter = Filter::new(
+            FilterConfig {
+                ema_alpha_permille: 200,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        assert_eq!(filter.apply(1000), 1000);
+        for voltage in [1050, 950].repeat(20) {
+            assert!(filter.apply(voltage).abs_diff(1000) <= 10);
+        }
+    }
+
+    #[test]
+    fn deadband_holds_until_the_voltage_clearly_moves() {
+        let mut filter = Filter::new(
+            FilterConfig {
+                deadband: 20,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        let outputs: Vec<u16> = [1000, 1015, 985, 1020, 1021, 1005, 1500]
+            .into_iter()
+            .map(|voltage| filter.apply(voltage))
+            .collect();
+        assert_eq!(outputs, [1000, 1000, 1000, 1000, 1021, 1021, 1500]);
+    }
+
+    #[test]
+    fn deadband_lets_the_end_stops_through() {
+        let mut filter = Filter::new(
+            FilterConfig {
+                deadband: 20,
+                ..UNFILTERED
+            },
+            0,
+            3000,
+        );
+        assert_eq!(filter.apply(2990), 2990);
+        assert_eq!(filter.apply(3300), 3000);
+        assert_eq!(filter.apply(10), 10);
+        assert_eq!(filter.apply(0), 0);
+    }
+
+    #[test]
+    fn all_stages_keep_a_noisy_spiky_knob_steady() {
+        let mut filter = Filter::new(
+            FilterConfig {
+                median_window: 5,
+                ema_alpha_permille: 300,
+                deadband: 20,
+            },
+            0,
+            3000,
+        );
+        let mut adc = FakeAdc::new(vec![1000, 1008, 992, 3300, 1004, 996, 0]);
+        let mut read = || {
+            let voltage = block_on(filter.average(&mut adc, 10));
+            filter.apply(voltage)
+        };
+        let first = read();
+        assert!(first.abs_diff(1000) <= 8);
+        for _ in 0..20 {
+            assert_eq!(read(), first);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Framing shared by the records kept in flash: a magic header telling records apart, the payload
+//! and a checksum over both
+
+pub const MAGIC_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 2;
+
+/// Length of a record around a payload of `payload_len` bytes
+pub const fn record_len(payload_len: usize) -> usize {
+    MAGIC_LEN + payload_len + CHECKSUM_LEN
+}
+
+/// Frames an `N`-byte record around the payload `write_payload` fills in, which starts zeroed
+pub fn encode<const N: usize>(
+    magic: [u8; MAGIC_LEN],
+    write_payload: impl FnOnce(&mut [u8]),
+) -> [u8; N] {
+    let mut record = [0; N];
+    record[..MAGIC_LEN].copy_from_slice(&magic);
+    write_payload(&mut record[MAGIC_LEN..N - CHECKSUM_LEN]);
+    let checksum = checksum(&record[..N - CHECKSUM_LEN]);
+    record[N - CHECKSUM_LEN..].copy_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+/// Payload of a `len`-byte record framed with the same magic. `None` for erased flash, data
+/// written by another firmware or a corrupted record
+pub fn decode(magic: [u8; MAGIC_LEN], bytes: &[u8], len: usize) -> Option<&[u8]> {
+    let bytes = bytes.get(..len.max(record_len(0)))?;
+    let (data, stored_checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    if !data.starts_with(&magic) || checksum(data).to_le_bytes() != stored_checksum {
+        return None;
+    }
+    Some(&data[MAGIC_LEN..])
+}
+
+fn checksum(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(0u16, |sum, &byte| sum.rotate_left(1) ^ byte as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; MAGIC_LEN] = *b"TST1";
+    const LEN: usize = record_len(3);
+
+    fn record() -> [u8; LEN] {
+        encode(MAGIC, |payload| payload.copy_from_slice(&[1, 2, 3]))
+    }
+
+    #[test]
+    fn payload_round_trips() {
+        assert_eq!(&record()[..MAGIC_LEN], &MAGIC);
+        assert_eq!(decode(MAGIC, &record(), LEN), Some(&[1, 2, 3][..]));
+        let mut longer = [0xFF; 2 * LEN];
+        longer[..LEN].copy_from_slice(&record());
+        assert_eq!(decode(MAGIC, &longer, LEN), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn foreign_or_damaged_records_are_rejected() {
+        assert_eq!(decode(MAGIC, &[0xFF; LEN], LEN), None);
+        assert_eq!(decode(*b"TST2", &record(), LEN), None);
+        assert_eq!(decode(MAGIC, &record()[..LEN - 1], LEN), None);
+        for index in 0..LEN {
+            let mut damaged = record();
+            damaged[index] ^= 0x10;
+            assert_eq!(decode(MAGIC, &damaged, LEN), None, "byte {}", index);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+/// How long the toy keeps going without any sign of someone playing with it
+#[derive(Debug, Clone, Copy)]
+pub struct InactivityConfig {
+    pub idle_timeout: u32,   // ms from the last activity until sleep
+    pub warning_period: u32, // ms before the timeout during which motion calms down
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityPhase {
+    Active,
+    Warning,
+    Expired,
+}
+
+impl ActivityPhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            ActivityPhase::Active => "active",
+            ActivityPhase::Warning => "warning",
+            ActivityPhase::Expired => "expired",
+        }
+    }
+}
+
+/// Counts down from the last activity, with all times in ms since boot
+#[derive(Debug)]
+pub struct InactivityTracker {
+    last_activity: u64,
+    active_until: u64,
+}
+
+impl InactivityTracker {
+    pub fn new(now: u64) -> Self {
+        Self {
+            last_activity: now,
+            active_until: now,
+        }
+    }
+
+    pub fn record_activity(&mut self, now: u64) {
+        self.last_activity = self.last_activity.max(now);
+    }
+
+    /// Holds off sleep until at least `deadline`, e.g. the end of a scheduled session
+    pub fn keep_active_until(&mut self, deadline: u64) {
+        self.active_until = self.active_until.max(deadline);
+    }
+
+    pub fn phase(&self, now: u64, config: &InactivityConfig) -> ActivityPhase {
+        let (warning_start, expiry) = self.deadlines(config);
+        if now >= expiry {
+            ActivityPhase::Expired
+        } else if now >= warning_start {
+            ActivityPhase::Warning
+        } else {
+            ActivityPhase::Active
+        }
+    }
+
+    /// When the phase changes next unless there is activity first, `None` once expired
+    pub fn next_phase_change(&self, now: u64, config: &InactivityConfig) -> Option<u64> {
+        let (warning_start, expiry) = self.deadlines(config);
+        match self.phase(now, config) {
+            ActivityPhase::Active => Some(warning_start),
+            ActivityPhase::Warning => Some(expiry),
+            ActivityPhase::Expired => None,
+        }
+    }
+
+    fn deadlines(&self, config: &InactivityConfig) -> (u64, u64) {
+        let expiry = (self.last_activity + config.idle_timeout as u64).max(self.active_until);
+        let warning_start = expiry - config.warning_period.min(config.idle_timeout) as u64;
+        (warning_start, expiry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: InactivityConfig = InactivityConfig {
+        idle_timeout: 10_000,
+        warning_period: 2_000,
+    };
+
+    #[test]
+    fn phase_changes_exactly_at_each_boundary() {
+        let tracker = InactivityTracker::new(1_000);
+        assert_eq!(tracker.phase(1_000, &CONFIG), ActivityPhase::Active);
+        assert_eq!(tracker.phase(8_999, &CONFIG), ActivityPhase::Active);
+        assert_eq!(tracker.phase(9_000, &CONFIG), ActivityPhase::Warning);
+        assert_eq!(tracker.phase(10_999, &CONFIG), ActivityPhase::Warning);
+        assert_eq!(tracker.phase(11_000, &CONFIG), ActivityPhase::Expired);
+    }
+
+    #[test]
+    fn next_phase_change_points_at_the_upcoming_boundary() {
+        let tracker = InactivityTracker::new(1_000);
+        assert_eq!(tracker.next_phase_change(1_000, &CONFIG), Some(9_000));
+        assert_eq!(tracker.next_phase_change(9_000, &CONFIG), Some(11_000));
+        assert_eq!(tracker.next_phase_change(11_000, &CONFIG), None);
+    }
+
+    #[test]
+    fn activity_restarts_the_countdown() {
+        let mut tracker = InactivityTracker::new(0);
+        tracker.record_activity(9_500);
+        assert_eq!(tracker.phase(9_500, &CONFIG), ActivityPhase::Active);
+        assert_eq!(tracker.next_phase_change(9_500, &CONFIG), Some(17_500));
+        // Late reports of earlier activity do not move the countdown back
+        tracker.record_activity(5_000);
+        assert_eq!(tracker.next_phase_change(9_500, &CONFIG), Some(17_500));
+    }
+
+    #[test]
+    fn keep_active_until_only_ever_pushes_the_deadline_out() {
+        let mut tracker = InactivityTracker::new(0);
+        tracker.keep_active_until(30_000);
+        assert_eq!(tracker.phase(20_000, &CONFIG), ActivityPhase::Active);
+        assert_eq!(tracker.next_phase_change(0, &CONFIG), Some(28_000));
+
+        tracker.keep_active_until(15_000);
+        assert_eq!(tracker.next_phase_change(0, &CONFIG), Some(28_000));
+        assert_eq!(tracker.phase(30_000, &CONFIG), ActivityPhase::Expired);
+
+        // A deadline before the idle timeout leaves the timeout in charge
+        let mut tracker = InactivityTracker::new(0);
+        tracker.keep_active_until(5_000);
+        assert_eq!(tracker.next_phase_change(0, &CONFIG), Some(8_000));
+    }
+
+    #[test]
+    fn warning_period_longer_than_the_timeout_starts_warning_right_away() {
+        let config = InactivityConfig {
+            idle_timeout: 1_000,
+            warning_period: 5_000,
+        };
+        let tracker = InactivityTracker::new(2_000);
+        assert_eq!(tracker.phase(2_000, &config), ActivityPhase::Warning);
+        assert_eq!(tracker.next_phase_change(2_000, &config), Some(3_000));
+    }
+}
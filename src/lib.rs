@@ -0,0 +1,30 @@
+//! Hardware-independent parts of the cat toy, kept apart from the firmware so they build and can be
+//! tested on the host
+#![cfg_attr(not(test), no_std)]
+// The traits are only implemented and awaited within this firmware's single-threaded executor
+#![allow(async_fn_in_trait)]
+extern crate alloc;
+
+pub mod battery;
+pub mod calibration;
+pub mod curve;
+pub mod duty;
+#[cfg(test)]
+mod fake_adc;
+pub mod filter;
+pub mod flash_record;
+pub mod inactivity;
+pub mod map_range;
+#[cfg(test)]
+mod mock_motor_driver;
+pub mod motion;
+pub mod motor_driver;
+pub mod pattern;
+pub mod potentiometer;
+pub mod power;
+pub mod ramp;
+pub mod recording;
+pub mod schedule;
+pub mod script;
+pub mod settings;
+pub mod sntp;
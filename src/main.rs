@@ -4,25 +4,40 @@
 #[macro_use]
 extern crate alloc;
 
-mod map_range;
 mod motor;
+mod sleep;
 
-use crate::map_range::map_range;
-use crate::motor::{Motor, MotorDirection};
-use alloc::string::ToString;
-use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use crate::motor::Motor;
+use crate::sleep::{WakeReason, WakeSources};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
 use core::{mem::MaybeUninit, str::from_utf8};
 use embassy_executor::Spawner;
+use embassy_futures::select::{select4, Either4};
+use embassy_futures::yield_now;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
 use embassy_net::{tcp::TcpSocket, Config, Stack, StackResources};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::{Duration, Instant, Ticker, Timer};
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::Watch;
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
 use embedded_io_async::Write;
+use embedded_storage::{ReadStorage, Storage};
 use esp_backtrace as _;
-use esp_hal::analog::adc::{Adc, AdcCalLine, AdcConfig, AdcPin, Attenuation};
-use esp_hal::gpio::GpioPin;
+use esp_hal::analog::adc::{Adc, AdcCalLine, AdcChannel, AdcConfig, AdcPin, Attenuation};
+use esp_hal::gpio::{GpioPin, Input, Pull, RtcPinWithResistors};
 use esp_hal::ledc::timer::TimerIFace;
-use esp_hal::peripherals::{ADC1, LPWR};
+use esp_hal::macros::ram;
+use esp_hal::peripherals::ADC1;
+use esp_hal::reset::get_wakeup_cause;
+use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::sleep::{RtcioWakeupSource, TimerWakeupSource, WakeSource, WakeupLevel};
 use esp_hal::rtc_cntl::Rtc;
 use esp_hal::timer::systimer::{SystemTimer, Target};
 use esp_hal::timer::timg::TimerGroup;
@@ -38,6 +53,7 @@ use esp_hal::{
     prelude::*,
     system::SystemControl,
 };
+use esp_storage::FlashStorage;
 use esp_wifi::{
     initialize,
     wifi::{
@@ -48,29 +64,253 @@ use esp_wifi::{
 };
 use log::{debug, error, info};
 use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use rand::SeedableRng;
 use serde::Serialize;
 use static_cell::StaticCell;
+use stepper_motor_cat_toy::battery::{BatteryConfig, BatteryMonitor};
+use stepper_motor_cat_toy::calibration::{
+    Calibration, CalibrationError, CalibrationRecorder, VoltageRange, CALIBRATION_LEN, MAX_KNOBS,
+};
+use stepper_motor_cat_toy::curve::Curve;
+use stepper_motor_cat_toy::duty::Duty;
+use stepper_motor_cat_toy::filter::FilterConfig;
+use stepper_motor_cat_toy::inactivity::{ActivityPhase, InactivityConfig, InactivityTracker};
+use stepper_motor_cat_toy::map_range::{map_range_clamped, Rounding};
+use stepper_motor_cat_toy::motion::{MotionEngine, MotionParameters, MotionStep};
+use stepper_motor_cat_toy::pattern::PatternKind;
+use stepper_motor_cat_toy::potentiometer::{AdcSource, PotentiometerConfig, PotentiometerMonitor};
+use stepper_motor_cat_toy::power::{LightSleepConfig, LightSleepStats, SupplyCurrent};
+use stepper_motor_cat_toy::ramp::{RampProfile, RampedMotor};
+use stepper_motor_cat_toy::recording::{Recording, RecordingMotor, Replay};
+use stepper_motor_cat_toy::schedule::{second_of_day, QuietHours, Schedule, SCHEDULE_LEN};
+use stepper_motor_cat_toy::script::Script;
+use stepper_motor_cat_toy::settings::{
+    Arbitration, DrasticChange, Parameter, ParameterSource, Settings, SettingsError,
+    SettingsLimits, SETTINGS_LEN,
+};
+use stepper_motor_cat_toy::sntp;
 
 const SSID: &str = env!("WIFI_SSID");
 const PASSWORD: &str = env!("WIFI_PASSWORD");
-static TEST: AtomicBool = AtomicBool::new(false);
+// Hardware entropy when unset, parsed here so a malformed seed fails the build rather than the boot
+const MOTION_RNG_SEED: Option<u64> = match option_env!("MOTION_RNG_SEED") {
+    Some(seed) => match u64::from_str_radix(seed, 10) {
+        Ok(seed) => Some(seed),
+        Err(_) => panic!("MOTION_RNG_SEED must be a decimal u64"),
+    },
+    None => None,
+};
 const BUFFER_SIZE: usize = 4096; // Number of bytes allocated for buffers
+const MOTION_START_ROUTES: [&str; 3] = ["POST /pattern/", "POST /script", "POST /replay"]; // Rejected during quiet hours
 
-const NUM_ADC_SAMPLES: usize = 100; // Number of ADC samples to average
-const MAX_ACTIVE_SEC: u16 = 10 * 60; // Number of seconds the device will be active before going to deep sleep
-const MIN_MOTOR_DUTY_PERCENT: u8 = 20;
-const MAX_MOTOR_DUTY_PERCENT: u8 = 100;
+const NUM_ADC_SAMPLES: u16 = 100; // Number of ADC samples to average
+const DEFAULT_IDLE_TIMEOUT: u16 = 10 * 60; // s without activity before going to deep sleep
+const DEFAULT_QUIET_HOURS: Option<QuietHours> = Some(QuietHours {
+    start: 22 * 60, // minutes after local midnight
+    end: 7 * 60,    // minutes after local midnight
+});
+const DEFAULT_LIGHT_SLEEP: bool = false; // Opt in on battery powered builds
+const DEFAULT_BATTERY_MONITORING: bool = false; // Opt in once the divider on GPIO4 is fitted, kept in flash
+const MIN_IDLE_TIMEOUT: u16 = 60; // s
+const MAX_IDLE_TIMEOUT: u16 = 60 * 60; // s
+const IDLE_WARNING_PERIOD: u16 = 60; // s before going to sleep in which motion calms down
+const IDLE_WARNING_SPEED_PERMILLE: u16 = 500; // Share of the speed range still used when calming down
+const IDLE_WARNING_PAUSE_PROBABILITY_PERCENT: u8 = 40;
+const DEEP_SLEEP_WAKE_SOURCES: WakeSources = WakeSources {
+    button: true,
+    timer: None, // Sleeps until the button is pressed
+};
+const MIN_MOTOR_DUTY: Duty = Duty::from_percent(20);
+const MAX_MOTOR_DUTY: Duty = Duty::from_percent(100);
 const MIN_MOVEMENT_DURATION: u16 = 200; // ms
 const MAX_MOVEMENT_DURATION: u16 = 2_000; // ms
+const SETTINGS_LIMITS: SettingsLimits = SettingsLimits {
+    min_motor_duty: MIN_MOTOR_DUTY,
+    max_motor_duty: MAX_MOTOR_DUTY,
+    min_movement_duration: MIN_MOVEMENT_DURATION,
+    max_movement_duration: MAX_MOVEMENT_DURATION,
+    min_idle_timeout: MIN_IDLE_TIMEOUT,
+    max_idle_timeout: MAX_IDLE_TIMEOUT,
+    min_battery_cutoff: MIN_BATTERY_CUTOFF,
+    max_battery_cutoff: MAX_BATTERY_CUTOFF,
+};
+const DEFAULT_SETTINGS: Settings = Settings {
+    min_motor_duty: MIN_MOTOR_DUTY,
+    max_motor_duty: MIN_MOTOR_DUTY,
+    min_movement_duration: MIN_MOVEMENT_DURATION,
+    max_movement_duration: MIN_MOVEMENT_DURATION,
+    idle_timeout: DEFAULT_IDLE_TIMEOUT,
+    quiet_hours: DEFAULT_QUIET_HOURS,
+    light_sleep: DEFAULT_LIGHT_SLEEP,
+    battery_monitoring: DEFAULT_BATTERY_MONITORING,
+    battery_cutoff: DEFAULT_BATTERY_CUTOFF,
+    test: false,
+    arbitration: Arbitration::RemoteUntilKnobMoves,
+    sources: [ParameterSource::Knob; Parameter::ALL.len()],
+};
+const DRASTIC_CHANGE: DrasticChange = DrasticChange {
+    motor_duty: Duty::from_percent(10),
+    movement_duration: 10, // ms
+};
+const SETTINGS_RECEIVERS: usize = 1; // Only the main loop waits for changes
 const POTENTIOMETER_READ_INTERVAL: u16 = 200; // ms
+const BATTERY_READ_INTERVAL: u16 = 5; // s
+const DEFAULT_BATTERY_CUTOFF: u16 = 3_300; // mV, keeps a single Li-ion cell well clear of damage
+const MIN_BATTERY_CUTOFF: u16 = 3_000; // mV
+const MAX_BATTERY_CUTOFF: u16 = 4_500; // mV
+const DEFAULT_PATTERN: PatternKind = PatternKind::RandomWalk;
+const MOVEMENT_RAMP_PROFILE: RampProfile = RampProfile::SCurve;
+const MAX_RAMP_DURATION: u16 = 300; // ms
+const PAUSE_PROBABILITY_PERCENT: u8 = 15;
+const MIN_PAUSE_DURATION: u16 = 500; // ms
+const MAX_PAUSE_DURATION: u16 = 3_000; // ms
+const PAUSE_BRAKE_DURATION: u16 = 100; // ms
+const SHUTDOWN_BRAKE_DURATION: u16 = 300; // ms
+const LIGHT_SLEEP: LightSleepConfig = LightSleepConfig {
+    min_nap: 100,       // ms
+    max_nap: 1_000,     // ms
+    wake_up_margin: 20, // ms
+};
+const LIGHT_SLEEP_AWAKE_WINDOW: u16 = 50; // ms between naps for the other tasks to catch up
+const SUPPLY_CURRENT: SupplyCurrent = SupplyCurrent {
+    active: 23_000,   // µA, CPU at 160 MHz with the radio idle
+    light_sleep: 130, // µA
+};
+const MOTOR_BRIDGE_PINS: [u8; 2] = [2, 3]; // GPIOs of the forward and reverse H-bridge inputs
+const SHUTDOWN_MOTOR_STOP_TIMEOUT: u16 = 2_000; // ms
+const MAX_RECORDING_SIZE: usize = 1536; // bytes, hex-encoded it must still fit into a buffer
+const DEFAULT_REPLAY_TIME_STRETCH: u16 = 1000; // permille
 
 const MIN_ADC_VOLTAGE: u16 = 0; // mV
 const MAX_ADC_VOLTAGE: u16 = 3000; // mV
+const CALIBRATION_BUTTON_HOLD: u16 = 2_000; // ms, at boot
+const CALIBRATION_DURATION: u16 = 10_000; // ms to sweep every knob between its end stops
+const MIN_CALIBRATED_SPAN: u16 = 500; // mV
+const TOY_FLASH_OFFSET: u32 = 0x3F_0000; // `toy` data partition in partitions.csv, clear of NVS and the PHY data
+const CALIBRATION_FLASH_OFFSET: u32 = TOY_FLASH_OFFSET; // First sector of the toy partition
+const SCHEDULE_FLASH_OFFSET: u32 = TOY_FLASH_OFFSET + 0x1000; // Second sector of the toy partition
+const SETTINGS_FLASH_OFFSET: u32 = TOY_FLASH_OFFSET + 0x2000; // Third sector of the toy partition
+const UTC_OFFSET_MINUTES: i16 = 0; // Local time minus UTC, play sessions are scheduled in local time
+const NTP_SERVER: &str = "pool.ntp.org";
+const SNTP_LOCAL_PORT: u16 = 50_123;
+const SNTP_TIMEOUT: u16 = 5_000; // ms
+const SNTP_RETRY_INTERVAL: u16 = 30; // s
+const WALL_CLOCK_SYNC_INTERVAL: u16 = 6 * 60 * 60; // s
+const POT_FILTER: FilterConfig = FilterConfig {
+    median_window: 5,
+    ema_alpha_permille: 400,
+    deadband: 20, // mV
+};
 
-static CURRENT_MAX_MOTOR_DUTY_PERCENT: AtomicU8 = AtomicU8::new(MIN_MOTOR_DUTY_PERCENT);
-static CURRENT_MAX_MOVEMENT_DURATION: AtomicU16 = AtomicU16::new(MIN_MOVEMENT_DURATION);
-static DRASTIC_PARAMETER_CHANGE: AtomicBool = AtomicBool::new(false);
+const BATTERY: BatteryConfig = BatteryConfig {
+    sample_count: NUM_ADC_SAMPLES,
+    filter: FilterConfig {
+        median_window: 5,
+        ema_alpha_permille: 200,
+        deadband: 0, // mV
+    },
+    divider_top: 100_000,    // Ω
+    divider_bottom: 100_000, // Ω
+    min_plausible: 1_000,    // mV, the chip cannot run on less
+    derate_start: 3_700,     // mV
+    derate_end: 3_400,       // mV
+    cutoff_readings: 3,
+};
+
+/// Every knob on the toy, read from the pin at the same position in `knob_pins` in `main`. The
+/// position also identifies the knob in the stored calibration
+const KNOBS: [PotentiometerConfig; 2] = [SPEED_POT, DURATION_POT];
+const _: () = assert!(KNOBS.len() <= MAX_KNOBS);
+
+const SPEED_POT: PotentiometerConfig = PotentiometerConfig {
+    name: "speed",
+    sample_count: NUM_ADC_SAMPLES,
+    filter: POT_FILTER,
+    min_voltage: MIN_ADC_VOLTAGE,
+    max_voltage: MAX_ADC_VOLTAGE,
+    min_output: MIN_MOTOR_DUTY.raw(),
+    max_output: MAX_MOTOR_DUTY.raw(),
+    curve: Curve::Exponential, // Most of the travel is spent at calm speeds
+    takeover_threshold: Duty::from_percent(5).raw(),
+    sink: |max_duty, moved_past_threshold| {
+        apply_knob(Parameter::Speed, max_duty, moved_past_threshold)
+    },
+};
+const DURATION_POT: PotentiometerConfig = PotentiometerConfig {
+    name: "duration",
+    sample_count: NUM_ADC_SAMPLES,
+    filter: POT_FILTER,
+    min_voltage: MIN_ADC_VOLTAGE,
+    max_voltage: MAX_ADC_VOLTAGE,
+    min_output: MIN_MOVEMENT_DURATION,
+    max_output: MAX_MOVEMENT_DURATION,
+    curve: Curve::Linear,
+    takeover_threshold: 100, // ms
+    sink: |max_duration, moved_past_threshold| {
+        apply_knob(Parameter::Duration, max_duration, moved_past_threshold)
+    },
+};
+
+static SETTINGS: Watch<CriticalSectionRawMutex, Settings, SETTINGS_RECEIVERS> =
+    Watch::new_with(DEFAULT_SETTINGS);
+/// Raised by commands that replace whatever movement is in progress
+static MOTION_INTERRUPTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static CURRENT_PATTERN: AtomicU8 = AtomicU8::new(DEFAULT_PATTERN as u8);
+static PATTERN_SELECTED: Signal<CriticalSectionRawMutex, PatternKind> = Signal::new();
+static SCRIPT_UPLOADED: Signal<CriticalSectionRawMutex, Script> = Signal::new();
+static SCRIPT_RUNNING: AtomicBool = AtomicBool::new(false);
+static RECORDING_COMMAND: Signal<CriticalSectionRawMutex, RecordingCommand> = Signal::new();
+static LAST_RECORDING: Mutex<CriticalSectionRawMutex, Option<Recording>> = Mutex::new(None);
+static RECORDING_ACTIVE: AtomicBool = AtomicBool::new(false);
+static REPLAY_RUNNING: AtomicBool = AtomicBool::new(false);
+static CALIBRATION: BlockingMutex<CriticalSectionRawMutex, Cell<Calibration>> =
+    BlockingMutex::new(Cell::new(Calibration::NONE));
+static CALIBRATION_RECORDER: BlockingMutex<
+    CriticalSectionRawMutex,
+    RefCell<Option<CalibrationRecorder>>,
+> = BlockingMutex::new(RefCell::new(None));
+static CALIBRATION_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static CALIBRATING: AtomicBool = AtomicBool::new(false);
+static KNOB_CURVES: BlockingMutex<CriticalSectionRawMutex, Cell<[Curve; KNOBS.len()]>> =
+    BlockingMutex::new(Cell::new(default_knob_curves()));
+static RNG_SEED: BlockingMutex<CriticalSectionRawMutex, Cell<u64>> =
+    BlockingMutex::new(Cell::new(0));
+static SCHEDULE: BlockingMutex<CriticalSectionRawMutex, Cell<Schedule>> =
+    BlockingMutex::new(Cell::new(Schedule::EMPTY));
+static WALL_CLOCK: BlockingMutex<CriticalSectionRawMutex, Cell<Option<u64>>> =
+    BlockingMutex::new(Cell::new(None)); // Unix time in ms at boot, once known
+static WALL_CLOCK_SET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Lives in RTC memory, which stays powered in deep sleep, unlike `WALL_CLOCK`
+#[ram(rtc_fast, persistent)]
+static mut RTC_WALL_CLOCK: [u64; 2] = [0; 2];
+static WAKE_REASON: BlockingMutex<CriticalSectionRawMutex, Cell<WakeReason>> =
+    BlockingMutex::new(Cell::new(WakeReason::PowerOn));
+static MAX_WAKE_UP_JITTER: AtomicU32 = AtomicU32::new(0); // µs
+static LIGHT_SLEEP_STATS: BlockingMutex<CriticalSectionRawMutex, Cell<LightSleepStats>> =
+    BlockingMutex::new(Cell::new(LightSleepStats {
+        naps: 0,
+        asleep: 0,
+        timer_lag: 0,
+    }));
+/// Raised by anything that shows someone is around: knob moves and API requests, play sensors
+/// should raise it too
+static ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static ACTIVITY_PHASE: BlockingMutex<CriticalSectionRawMutex, Cell<ActivityPhase>> =
+    BlockingMutex::new(Cell::new(ActivityPhase::Active));
+static BATTERY_VOLTAGE: BlockingMutex<CriticalSectionRawMutex, Cell<Option<u16>>> =
+    BlockingMutex::new(Cell::new(None)); // mV, once measured through a fitted divider
+static LOW_BATTERY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static SHUTDOWN_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static MOTOR_STOPPED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+enum RecordingCommand {
+    Start,
+    Stop,
+    Replay {
+        time_stretch_permille: u16,
+        mirrored: bool,
+    },
+}
 
 #[derive(Serialize)]
 struct CurrentState {
@@ -78,31 +318,275 @@ struct CurrentState {
     pub max_duty_percent: u8,
     pub min_movement_duration: u16,
     pub max_movement_duration: u16,
+    pub current_min_motor_duty_percent: u8,
     pub current_max_motor_duty_percent: u8,
+    pub current_min_movement_duration: u16,
     pub current_max_movement_duration: u16,
+    pub arbitration: &'static str,
+    pub speed_source: &'static str,
+    pub duration_source: &'static str,
+    pub pattern: &'static str,
+    pub script_running: bool,
+    pub recording: bool,
+    pub replay_running: bool,
+    pub wake_reason: &'static str,
+    pub idle_timeout: u16,
+    pub activity_phase: &'static str,
+    pub unix_time: Option<u64>,
+    pub next_session_in: Option<u32>, // s
+    pub quiet_hours: Option<QuietHours>,
+    pub quiet_hours_active: bool,
+    pub rng_seed: u64,
+    pub max_wake_up_jitter_us: u32,
+    pub battery_monitoring: bool,
+    pub battery_voltage: Option<u16>, // mV
+    pub battery_cutoff: u16,          // mV
+    pub light_sleep: bool,
+    pub light_sleep_permille: u16,
+    pub estimated_current_saving_ua: u32,
+    pub calibrating: bool,
+    pub knobs: [KnobState; KNOBS.len()],
+}
+#[derive(Serialize)]
+struct KnobState {
+    pub name: &'static str,
+    pub range: VoltageRange,
+    pub calibrated: bool,
+    pub curve: &'static str,
 }
-const CURRENT_STATE_SERIALIZED_LEN: usize = 300;
+const CURRENT_STATE_SERIALIZED_LEN: usize = 1536; // The longest state takes about 1100 bytes
 impl CurrentState {
-    pub fn get_json_str() -> serde_json_core::heapless::String<CURRENT_STATE_SERIALIZED_LEN> {
+    pub fn get_json_str() -> Result<
+        serde_json_core::heapless::String<CURRENT_STATE_SERIALIZED_LEN>,
+        serde_json_core::ser::Error,
+    > {
+        let calibration = CALIBRATION.lock(Cell::get);
+        let curves = KNOB_CURVES.lock(Cell::get);
+        let settings = current_settings();
+        let light_sleep_stats = LIGHT_SLEEP_STATS.lock(Cell::get);
+        let timer_now = Instant::now().as_millis();
         let current_state = Self {
-            min_duty_percent: MIN_MOTOR_DUTY_PERCENT,
-            max_duty_percent: MAX_MOTOR_DUTY_PERCENT,
+            min_duty_percent: MIN_MOTOR_DUTY.percent(),
+            max_duty_percent: MAX_MOTOR_DUTY.percent(),
             min_movement_duration: MIN_MOVEMENT_DURATION,
             max_movement_duration: MAX_MOVEMENT_DURATION,
-            current_max_motor_duty_percent: CURRENT_MAX_MOTOR_DUTY_PERCENT.load(Ordering::Relaxed),
-            current_max_movement_duration: CURRENT_MAX_MOVEMENT_DURATION.load(Ordering::Relaxed),
+            current_min_motor_duty_percent: settings.min_motor_duty.percent(),
+            current_max_motor_duty_percent: settings.max_motor_duty.percent(),
+            current_min_movement_duration: settings.min_movement_duration,
+            current_max_movement_duration: settings.max_movement_duration,
+            arbitration: settings.arbitration.name(),
+            speed_source: settings.source(Parameter::Speed).name(),
+            duration_source: settings.source(Parameter::Duration).name(),
+            pattern: current_pattern().name(),
+            script_running: SCRIPT_RUNNING.load(Ordering::Relaxed),
+            recording: RECORDING_ACTIVE.load(Ordering::Relaxed),
+            replay_running: REPLAY_RUNNING.load(Ordering::Relaxed),
+            wake_reason: WAKE_REASON.lock(Cell::get).name(),
+            idle_timeout: settings.idle_timeout,
+            activity_phase: ACTIVITY_PHASE.lock(Cell::get).name(),
+            unix_time: WALL_CLOCK
+                .lock(Cell::get)
+                .map(|boot_time| (boot_time + uptime_ms()) / 1000),
+            next_session_in: local_second_of_day().and_then(|second| {
+                SCHEDULE
+                    .lock(Cell::get)
+                    .until_next_session(second, settings.quiet_hours)
+            }),
+            quiet_hours: settings.quiet_hours,
+            quiet_hours_active: active_quiet_hours().is_some(),
+            rng_seed: RNG_SEED.lock(|seed| seed.get()),
+            max_wake_up_jitter_us: MAX_WAKE_UP_JITTER.load(Ordering::Relaxed),
+            battery_monitoring: settings.battery_monitoring,
+            battery_voltage: BATTERY_VOLTAGE.lock(Cell::get),
+            battery_cutoff: settings.battery_cutoff,
+            light_sleep: settings.light_sleep,
+            light_sleep_permille: light_sleep_stats.asleep_permille(timer_now),
+            estimated_current_saving_ua: light_sleep_stats
+                .estimated_saving(timer_now, &SUPPLY_CURRENT),
+            calibrating: CALIBRATING.load(Ordering::Relaxed),
+            knobs: core::array::from_fn(|knob| KnobState {
+                name: KNOBS[knob].name,
+                range: knob_voltage_range(&calibration, knob),
+                calibrated: calibration.range(knob).is_some(),
+                curve: curves[knob].name(),
+            }),
         };
 
-        serde_json_core::to_string(&current_state).unwrap()
+        serde_json_core::to_string(&current_state)
     }
 }
 
+/// `status` is the code followed by its reason phrase, e.g. `200 OK`
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn json_response(status: &str, body: &str) -> String {
+    http_response(status, "application/json", body)
+}
+
+fn text_response(status: &str, body: &str) -> String {
+    http_response(status, "text/plain", body)
+}
+
+/// The current state as a JSON response, or a server error should it outgrow its buffer
+fn state_response() -> String {
+    match CurrentState::get_json_str() {
+        Ok(body) => json_response("200 OK", &body),
+        Err(e) => {
+            error!("Failed to serialize the current state: {:?}", e);
+            text_response(
+                "500 Internal Server Error",
+                "State does not fit into its buffer",
+            )
+        }
+    }
+}
+
+fn current_settings() -> Settings {
+    SETTINGS.try_get().unwrap_or(DEFAULT_SETTINGS)
+}
+
+/// The only way settings are written: the edit is validated as a whole and receivers are only
+/// woken when something actually changed
+fn update_settings(edit: impl Fn(&mut Settings)) -> Result<Settings, SettingsError> {
+    let result = Cell::new(Ok(current_settings()));
+    SETTINGS.sender().send_if_modified(|settings| {
+        let Some(settings) = settings else {
+            return false;
+        };
+        let mut updated = *settings;
+        edit(&mut updated);
+        if let Err(e) = updated.validate(&SETTINGS_LIMITS) {
+            result.set(Err(e));
+            return false;
+        }
+        result.set(Ok(updated));
+        let modified = updated != *settings;
+        *settings = updated;
+        modified
+    });
+    result.get()
+}
+
+/// Keeps the settings that have to survive deep sleep and power loss in flash
+fn save_settings(settings: &Settings) {
+    match FlashStorage::new().write(SETTINGS_FLASH_OFFSET, &settings.to_bytes()) {
+        Ok(()) => debug!("Settings saved"),
+        Err(e) => error!("Failed to save the settings: {:?}", e),
+    }
+}
+
+/// Hands a knob's output to the settings, subject to the arbitration policy
+fn apply_knob(parameter: Parameter, value: u16, moved_past_threshold: bool) -> bool {
+    ACTIVITY.signal(());
+    let applied = Cell::new(false);
+    if let Err(e) = update_settings(|settings| {
+        applied.set(settings.apply_knob(parameter, value, moved_past_threshold))
+    }) {
+        error!("{} knob setting rejected: {}", parameter.name(), e);
+        return false;
+    }
+    applied.get()
+}
+
+/// Value of `key` in the query string of a request path, e.g. `?min=20&max=80 HTTP/1.1`
+fn query_param<'a>(path: &'a str, key: &str) -> Option<&'a str> {
+    let query = path.split(' ').next()?.strip_prefix('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(name, value)| (name == key).then_some(value))
+}
+
+/// ms since boot, including the time the system timer missed during light sleep
+fn uptime_ms() -> u64 {
+    LIGHT_SLEEP_STATS
+        .lock(Cell::get)
+        .uptime(Instant::now().as_millis())
+}
+
+/// Seconds since local midnight, once the wall clock has been set over SNTP
+fn local_second_of_day() -> Option<u32> {
+    let unix_time = WALL_CLOCK.lock(Cell::get)? + uptime_ms();
+    Some(second_of_day(unix_time / 1000, UTC_OFFSET_MINUTES))
+}
+
+/// The configured quiet hours while they are in effect
+fn active_quiet_hours() -> Option<QuietHours> {
+    let second_of_day = local_second_of_day()?;
+    current_settings()
+        .quiet_hours
+        .filter(|quiet_hours| quiet_hours.contains(second_of_day))
+}
+
+const fn default_knob_curves() -> [Curve; KNOBS.len()] {
+    let mut curves = [Curve::Linear; KNOBS.len()];
+    let mut knob = 0;
+    while knob < KNOBS.len() {
+        curves[knob] = KNOBS[knob].curve;
+        knob += 1;
+    }
+    curves
+}
+
+/// The calibrated range of a knob, or its nominal one until it has been calibrated
+fn knob_voltage_range(calibration: &Calibration, knob: usize) -> VoltageRange {
+    calibration.range(knob).unwrap_or(VoltageRange {
+        min: KNOBS[knob].min_voltage,
+        max: KNOBS[knob].max_voltage,
+    })
+}
+
+fn current_pattern() -> PatternKind {
+    PatternKind::from_index(CURRENT_PATTERN.load(Ordering::Relaxed)).unwrap_or(DEFAULT_PATTERN)
+}
+
 type Adc1Calibration = AdcCalLine<ADC1>;
 type Adc1Mutex = Mutex<CriticalSectionRawMutex, Adc<'static, ADC1>>;
-type AdcPin0MutexForSpeed =
-    Mutex<CriticalSectionRawMutex, AdcPin<GpioPin<0>, ADC1, Adc1Calibration>>;
-type AdcPin1MutexForDuration =
-    Mutex<CriticalSectionRawMutex, AdcPin<GpioPin<1>, ADC1, Adc1Calibration>>;
+type BatteryPin = AdcPin<GpioPin<4>, ADC1, Adc1Calibration>;
+type RtcMutex = Mutex<CriticalSectionRawMutex, Rtc<'static>>;
+
+/// ADC1 pin on any GPIO, so pins of different types fit in one array
+trait AdcInput {
+    fn read_oneshot(&mut self, adc1: &mut Adc<'static, ADC1>) -> nb::Result<u16, ()>;
+}
+
+impl<PIN: AdcChannel> AdcInput for AdcPin<PIN, ADC1, Adc1Calibration> {
+    fn read_oneshot(&mut self, adc1: &mut Adc<'static, ADC1>) -> nb::Result<u16, ()> {
+        adc1.read_oneshot(self)
+    }
+}
+
+/// ADC pin sampled while holding the ADC
+struct AnalogPin<'a> {
+    adc1: &'a mut Adc<'static, ADC1>,
+    pin: &'a mut dyn AdcInput,
+}
+
+impl AdcSource for AnalogPin<'_> {
+    async fn read_mv(&mut self) -> u16 {
+        loop {
+            match self.pin.read_oneshot(self.adc1) {
+                Err(nb::Error::WouldBlock) => yield_now().await,
+                result => return result.unwrap(),
+            }
+        }
+    }
+}
+
+/// Called by esp-backtrace before it prints the backtrace and halts, as LEDC would otherwise keep
+/// driving the motor with the last duty
+#[no_mangle]
+fn custom_pre_backtrace() {
+    motor::force_inputs_low(&MOTOR_BRIDGE_PINS);
+}
 
 // When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
 macro_rules! mk_static {
@@ -132,6 +616,10 @@ async fn main(spawner: Spawner) {
     info!("Started");
     init_heap();
 
+    let wake_reason = WakeReason::from(get_wakeup_cause());
+    info!("Wake reason: {}", wake_reason.name());
+    WAKE_REASON.lock(|reason| reason.set(wake_reason));
+
     let peripherals = Peripherals::take();
 
     let system = SystemControl::new(peripherals.SYSTEM);
@@ -139,10 +627,11 @@ async fn main(spawner: Spawner) {
 
     let timg0 = TimerGroup::new(peripherals.TIMG0, &clocks);
 
+    let mut hardware_rng = Rng::new(peripherals.RNG);
     let init = initialize(
         EspWifiInitFor::Wifi,
         timg0.timer0,
-        esp_hal::rng::Rng::new(peripherals.RNG),
+        hardware_rng,
         peripherals.RADIO_CLK,
         &clocks,
     )
@@ -168,13 +657,14 @@ async fn main(spawner: Spawner) {
         )
     );
 
-    // spawner.spawn(connection(controller)).ok();
-    // spawner.must_spawn(net_task(stack));
-    // spawner.must_spawn(start_web_server(stack));
+    spawner.must_spawn(connection(controller));
+    spawner.must_spawn(net_task(stack));
+    spawner.must_spawn(start_web_server(stack));
+    spawner.must_spawn(sync_wall_clock(stack));
 
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
-    let motor_pwm_pin_forward = io.pins.gpio2;
-    let motor_pwm_pin_reverse = io.pins.gpio3;
+    let motor_pwm_pin_forward = io.pins.gpio2; // MOTOR_BRIDGE_PINS[0]
+    let motor_pwm_pin_reverse = io.pins.gpio3; // MOTOR_BRIDGE_PINS[1]
 
     // Instantiate PWM infra
     let mut ledc_pwm_controller = Ledc::new(peripherals.LEDC, &clocks);
@@ -188,161 +678,535 @@ async fn main(spawner: Spawner) {
         })
         .unwrap();
 
-    let mut motor = Motor::new(
-        &ledc_pwm_controller,
-        &pwm_timer,
-        channel::Number::Channel0,
-        channel::Number::Channel1,
-        motor_pwm_pin_forward,
-        motor_pwm_pin_reverse,
+    let mut motor = RecordingMotor::new(
+        RampedMotor::new(Motor::new(
+            &ledc_pwm_controller,
+            &pwm_timer,
+            channel::Number::Channel0,
+            channel::Number::Channel1,
+            motor_pwm_pin_forward,
+            motor_pwm_pin_reverse,
+        )),
+        || Instant::now().as_millis(),
     );
 
     // Instantiate ADC and mutexes
     let mut adc1_config = AdcConfig::new();
-    static SPEED_STATIC_CELL: StaticCell<AdcPin0MutexForSpeed> = StaticCell::new();
-    let speed_pot_pin = SPEED_STATIC_CELL.init(Mutex::new(
-        adc1_config
-            .enable_pin_with_cal::<_, Adc1Calibration>(io.pins.gpio0, Attenuation::Attenuation11dB),
-    ));
-    static DURATION_STATIC_CELL: StaticCell<AdcPin1MutexForDuration> = StaticCell::new();
-    let duration_pot_pin = DURATION_STATIC_CELL.init(Mutex::new(
-        adc1_config
-            .enable_pin_with_cal::<_, Adc1Calibration>(io.pins.gpio1, Attenuation::Attenuation11dB),
-    ));
+    // One pin per entry in `KNOBS`, in the same order
+    let knob_pins: [Box<dyn AdcInput>; KNOBS.len()] = [
+        Box::new(adc1_config.enable_pin_with_cal::<_, Adc1Calibration>(
+            io.pins.gpio0,
+            Attenuation::Attenuation11dB,
+        )),
+        Box::new(adc1_config.enable_pin_with_cal::<_, Adc1Calibration>(
+            io.pins.gpio1,
+            Attenuation::Attenuation11dB,
+        )),
+    ];
+    let battery_pin = adc1_config
+        .enable_pin_with_cal::<_, Adc1Calibration>(io.pins.gpio4, Attenuation::Attenuation11dB);
     let adc1 = Adc::new(peripherals.ADC1, adc1_config);
     static ADC1_MUTEX: StaticCell<Adc1Mutex> = StaticCell::new();
     let adc1 = ADC1_MUTEX.init(Mutex::new(adc1));
 
-    let low_power_peripheral = peripherals.LPWR;
-    spawner.must_spawn(deep_sleep_countdown(low_power_peripheral));
-    spawner.must_spawn(monitor_speed_pot(adc1, speed_pot_pin));
-    spawner.must_spawn(monitor_duration_pot(adc1, duration_pot_pin));
+    // Restore the knob calibration, or start one if the button is held through boot
+    let mut flash = FlashStorage::new();
+    let mut calibration_bytes = [0; CALIBRATION_LEN];
+    match flash.read(CALIBRATION_FLASH_OFFSET, &mut calibration_bytes) {
+        Ok(()) => match Calibration::from_bytes(&calibration_bytes) {
+            Some(calibration) => {
+                info!("Restored knob calibration");
+                CALIBRATION.lock(|stored| stored.set(calibration));
+            }
+            None => info!("No knob calibration stored, using the nominal voltage range"),
+        },
+        Err(e) => error!("Failed to read knob calibration: {:?}", e),
+    }
+    let mut schedule_bytes = [0; SCHEDULE_LEN];
+    match flash.read(SCHEDULE_FLASH_OFFSET, &mut schedule_bytes) {
+        Ok(()) => match Schedule::from_bytes(&schedule_bytes) {
+            Some(schedule) => {
+                info!("Restored {} scheduled sessions", schedule.slots().len());
+                SCHEDULE.lock(|stored| stored.set(schedule));
+            }
+            None => info!("No play sessions scheduled"),
+        },
+        Err(e) => error!("Failed to read the schedule: {:?}", e),
+    }
+    let mut settings_bytes = [0; SETTINGS_LEN];
+    match flash.read(SETTINGS_FLASH_OFFSET, &mut settings_bytes) {
+        Ok(()) => match DEFAULT_SETTINGS.with_stored(&settings_bytes) {
+            Some(settings) => match settings.validate(&SETTINGS_LIMITS) {
+                Ok(()) => {
+                    info!("Restored the saved settings");
+                    SETTINGS.sender().send(settings);
+                }
+                Err(e) => error!("Ignoring the saved settings: {}", e),
+            },
+            None => info!("No settings saved, using the defaults"),
+        },
+        Err(e) => error!("Failed to read the saved settings: {:?}", e),
+    }
+    let mut button_pin = io.pins.gpio5;
+    {
+        let mut calibration_button = Input::new(&mut button_pin, Pull::Up);
+        if calibration_button.is_low()
+            && with_timeout(
+                Duration::from_millis(CALIBRATION_BUTTON_HOLD.into()),
+                calibration_button.wait_for_high(),
+            )
+            .await
+            .is_err()
+        {
+            CALIBRATION_REQUESTED.signal(());
+        }
+    }
+
+    let rtc = Rtc::new(peripherals.LPWR);
+    // SAFETY: the executor has not started anything that could write it yet
+    let rtc_wall_clock = unsafe { RTC_WALL_CLOCK };
+    if let Some(unix_time) = sntp::restore_wall_clock(rtc_wall_clock, rtc.get_time_ms())
+        .filter(|_| wake_reason != WakeReason::PowerOn)
+    {
+        WALL_CLOCK.lock(|clock| clock.set(Some(unix_time - uptime_ms())));
+        info!("Wall clock kept through deep sleep");
+    }
+    static RTC_MUTEX: StaticCell<RtcMutex> = StaticCell::new();
+    let rtc = RTC_MUTEX.init(Mutex::new(rtc));
+    spawner.must_spawn(sleep_when_inactive(rtc, button_pin));
+    spawner.must_spawn(calibrate_potentiometers(flash));
+    spawner.must_spawn(monitor_knobs(adc1, knob_pins));
+    spawner.must_spawn(monitor_battery(adc1, battery_pin));
 
     // Main loop
-    let mut small_rng = SmallRng::seed_from_u64(1); // Seed is irrelevant for random number generation
-    let mut ticker = Ticker::every(Duration::from_millis(POTENTIOMETER_READ_INTERVAL.into()));
-    for direction in [MotorDirection::Forward, MotorDirection::Reverse]
-        .iter()
-        .cycle()
-    {
-        let start_time = Instant::now();
-        let max_motor_percent = CURRENT_MAX_MOTOR_DUTY_PERCENT.load(Ordering::Relaxed);
-        let duty_percent = small_rng.gen_range(MIN_MOTOR_DUTY_PERCENT..=max_motor_percent);
+    let rng_seed = match MOTION_RNG_SEED {
+        Some(seed) => seed,
+        None => (hardware_rng.random() as u64) << 32 | hardware_rng.random() as u64,
+    };
+    info!("Motion RNG seed: {}", rng_seed);
+    RNG_SEED.lock(|seed| seed.set(rng_seed));
+    let small_rng = SmallRng::seed_from_u64(rng_seed);
+    let mut motion_engine = MotionEngine::new(small_rng, current_pattern());
+    let mut settings_receiver = SETTINGS.receiver().unwrap();
+    let mut settings = current_settings();
+    loop {
+        if let Some(pattern) = PATTERN_SELECTED.try_take() {
+            info!("Switching to the {} pattern", pattern.name());
+            motion_engine.set_pattern(pattern);
+        }
+        if let Some(script) = SCRIPT_UPLOADED.try_take() {
+            info!("Running uploaded script");
+            motion_engine.run_script(script);
+        }
+        if let Some(command) = RECORDING_COMMAND.try_take() {
+            match command {
+                RecordingCommand::Start => {
+                    info!("Recording started");
+                    motor.start_recording(MAX_RECORDING_SIZE);
+                }
+                RecordingCommand::Stop => {
+                    if let Some(recording) = motor.stop_recording() {
+                        info!("Recording stopped");
+                        *LAST_RECORDING.lock().await = Some(recording);
+                    }
+                }
+                RecordingCommand::Replay {
+                    time_stretch_permille,
+                    mirrored,
+                } => match LAST_RECORDING.lock().await.as_ref() {
+                    Some(recording) => {
+                        match Replay::new(recording, time_stretch_permille, mirrored) {
+                            Ok(replay) => {
+                                info!("Replaying recording");
+                                motion_engine.run_replay(replay);
+                            }
+                            Err(e) => error!("Recording could not be replayed: {}", e),
+                        }
+                    }
+                    None => info!("No recording to replay"),
+                },
+            }
+        }
+        SCRIPT_RUNNING.store(motion_engine.is_running_script(), Ordering::Relaxed);
+        REPLAY_RUNNING.store(motion_engine.is_replaying(), Ordering::Relaxed);
+        RECORDING_ACTIVE.store(motor.is_recording(), Ordering::Relaxed);
 
-        let max_movement_duration = CURRENT_MAX_MOVEMENT_DURATION.load(Ordering::Relaxed);
-        let movement_duration_ms =
-            small_rng.gen_range(MIN_MOVEMENT_DURATION..=max_movement_duration);
-        let movement_duration = Duration::from_millis(movement_duration_ms.into());
+        // Calm down before going to sleep so the session fades out instead of stopping abruptly
+        let (max_duty, pause_probability_percent) =
+            if ACTIVITY_PHASE.lock(Cell::get) == ActivityPhase::Warning {
+                let calm_max_duty = map_range_clamped(
+                    IDLE_WARNING_SPEED_PERMILLE,
+                    0,
+                    1000,
+                    settings.min_motor_duty.raw(),
+                    settings.max_motor_duty.raw(),
+                    Rounding::Floor,
+                )
+                .map_or(settings.min_motor_duty, Duty::from_raw);
+                (calm_max_duty, IDLE_WARNING_PAUSE_PROBABILITY_PERCENT)
+            } else {
+                (settings.max_motor_duty, PAUSE_PROBABILITY_PERCENT)
+            };
 
-        motor.start_movement(direction, duty_percent);
-        debug!(
-            "Movement started: {:?} @ {}% for {} ms",
-            direction, duty_percent, movement_duration_ms
-        );
-        DRASTIC_PARAMETER_CHANGE.store(false, Ordering::Relaxed);
+        // Ease off the motor as the supply sags instead of browning out under load
+        let max_duty = BATTERY_VOLTAGE.lock(Cell::get).map_or(max_duty, |voltage| {
+            BATTERY.derate(voltage, settings.min_motor_duty, max_duty)
+        });
 
-        while Instant::now().duration_since(start_time) <= movement_duration {
-            ticker.next().await;
-            if DRASTIC_PARAMETER_CHANGE.load(Ordering::Relaxed) {
-                debug!("Drastic parameter change detected, breaking loop");
-                break; // Break the loop if there is a drastic parameter change
+        let start_time = Instant::now();
+        let motion_parameters = MotionParameters {
+            min_duty: settings.min_motor_duty,
+            max_duty,
+            min_movement_duration: settings.min_movement_duration,
+            max_movement_duration: settings.max_movement_duration,
+            ramp_profile: MOVEMENT_RAMP_PROFILE,
+            max_ramp_duration: MAX_RAMP_DURATION,
+            pause_probability_percent,
+            min_pause_duration: MIN_PAUSE_DURATION,
+            max_pause_duration: MAX_PAUSE_DURATION,
+        };
+        let quiet = active_quiet_hours().is_some();
+        if quiet != motion_engine.is_quiet() {
+            match quiet {
+                true => info!("Quiet hours started, holding the motor stopped"),
+                false => info!("Quiet hours ended"),
+            }
+            motion_engine.set_quiet(quiet);
+        }
+        let step = motion_engine.next_step(&motion_parameters);
+        let movement_duration = Duration::from_millis(step.duration_ms().into());
+        let may_nap = settings.light_sleep && matches!(step, MotionStep::Pause { .. });
+
+        match step {
+            MotionStep::Move {
+                direction,
+                duty,
+                duration_ms,
+                ramp,
+            } => {
+                motor.ramp_to(direction, duty, ramp).await;
+                debug!(
+                    "Movement started: {:?} @ {} for {} ms ({:?} ramp over {} ms)",
+                    direction, duty, duration_ms, ramp.profile, ramp.duration_ms
+                );
+            }
+            MotionStep::Pause { duration_ms } => {
+                // Freeze like prey would instead of drifting to a halt
+                motor
+                    .brake_then_coast(Duration::from_millis(PAUSE_BRAKE_DURATION.into()))
+                    .await;
+                debug!("Pause started for {} ms", duration_ms);
+            }
+        }
+
+        // Sleep until the movement ends, waking early only for changes that cannot wait for it
+        let mut movement_end = start_time + movement_duration;
+        let light_sleep_awake_window = Duration::from_millis(LIGHT_SLEEP_AWAKE_WINDOW.into());
+        let mut nap_at = may_nap.then(|| Instant::now() + light_sleep_awake_window);
+        loop {
+            let wake_up_at = nap_at.map_or(movement_end, |nap_at| nap_at.min(movement_end));
+            match select4(
+                Timer::at(wake_up_at),
+                SHUTDOWN_REQUESTED.wait(),
+                settings_receiver.changed(),
+                MOTION_INTERRUPTED.wait(),
+            )
+            .await
+            {
+                Either4::First(()) if Instant::now() < movement_end => {
+                    // The other tasks had their turn, so the CPU can stop until the pause ends
+                    nap_at = match light_sleep_until(rtc, movement_end).await {
+                        Some(timer_lag) => {
+                            movement_end =
+                                movement_end.checked_sub(timer_lag).unwrap_or(movement_end);
+                            Some(Instant::now() + light_sleep_awake_window)
+                        }
+                        None => None,
+                    };
+                }
+                Either4::First(()) => {
+                    record_wake_up_jitter(Instant::now().saturating_duration_since(movement_end));
+                    break;
+                }
+                Either4::Second(()) => {
+                    info!("Shutdown requested, stopping motor");
+                    motor
+                        .brake_then_coast(Duration::from_millis(SHUTDOWN_BRAKE_DURATION.into()))
+                        .await;
+                    MOTOR_STOPPED.signal(());
+                    return;
+                }
+                Either4::Third(changed) => {
+                    let previous = core::mem::replace(&mut settings, changed);
+                    if settings.is_drastic_change_from(&previous, &DRASTIC_CHANGE) {
+                        debug!("Drastic parameter change detected, breaking loop");
+                        break;
+                    }
+                }
+                Either4::Fourth(()) => {
+                    debug!("Motion interrupted, breaking loop");
+                    break;
+                }
             }
         }
     }
 }
 
-#[embassy_executor::task]
-async fn deep_sleep_countdown(low_power_peripheral: LPWR) {
-    Timer::after(Duration::from_secs(MAX_ACTIVE_SEC.into())).await;
-    info!("{} seconds passed, going to deep sleep", MAX_ACTIVE_SEC);
-    let mut rtc = Rtc::new(low_power_peripheral);
-    rtc.sleep_deep(&[]);
+/// Naps in light sleep until shortly before `deadline`, returning how far the system timer fell
+/// behind meanwhile, or `None` when the time left is too short to nap
+async fn light_sleep_until(rtc_mutex: &RtcMutex, deadline: Instant) -> Option<Duration> {
+    let fell_asleep = Instant::now();
+    let nap = LIGHT_SLEEP.nap_duration(fell_asleep.as_millis(), deadline.as_millis())?;
+    let mut rtc = rtc_mutex.lock().await;
+    // Pauses leave both PWM channels at 0 % duty, which the LEDC keeps outputting as a steady low
+    // level while its clock is stopped, so the motor stays off
+    let timer_wake_source = TimerWakeupSource::new(core::time::Duration::from_millis(nap.into()));
+    let rtc_fell_asleep = rtc.get_time_ms();
+    rtc.sleep_light(&[&timer_wake_source]);
+    let slept = rtc.get_time_ms() - rtc_fell_asleep;
+    let timer_elapsed = fell_asleep.elapsed().as_millis();
+    let stats = LIGHT_SLEEP_STATS.lock(|stats| {
+        let mut updated = stats.get();
+        updated.record_nap(slept, timer_elapsed);
+        stats.set(updated);
+        updated
+    });
+    debug!("Napped for {} ms, {} naps since boot", slept, stats.naps);
+    Some(Duration::from_millis(slept.saturating_sub(timer_elapsed)))
+}
+
+/// Tracks how late the main loop wakes up, which grows when other tasks hog the executor
+fn record_wake_up_jitter(lateness: Duration) {
+    let jitter = lateness.as_micros().min(u32::MAX.into()) as u32;
+    if jitter > MAX_WAKE_UP_JITTER.load(Ordering::Relaxed) {
+        MAX_WAKE_UP_JITTER.store(jitter, Ordering::Relaxed);
+        debug!("New maximum main loop wake-up jitter: {} µs", jitter);
+    }
 }
 
 #[embassy_executor::task]
-async fn monitor_speed_pot(
-    adc1_mutex: &'static Adc1Mutex,
-    speed_pot_pin_mutex: &'static AdcPin0MutexForSpeed,
-) {
-    let mut ticker = Ticker::every(Duration::from_millis(POTENTIOMETER_READ_INTERVAL.into()));
-    let mut prev_max_duty_percent = MIN_MOTOR_DUTY_PERCENT;
-    loop {
-        debug!("Checking speed pot pin (#0)");
+async fn sleep_when_inactive(rtc_mutex: &'static RtcMutex, mut button_pin: GpioPin<5>) {
+    let mut tracker = InactivityTracker::new(Instant::now().as_millis());
+    let low_battery = loop {
+        let config = InactivityConfig {
+            idle_timeout: current_settings().idle_timeout as u32 * 1000,
+            warning_period: IDLE_WARNING_PERIOD as u32 * 1000,
+        };
+        if let Some(quiet_hours) = active_quiet_hours() {
+            info!("Quiet hours {} started, going to deep sleep", quiet_hours);
+            break false;
+        }
+
+        let now = Instant::now().as_millis();
+        let second_of_day = local_second_of_day();
+        let quiet_hours = current_settings().quiet_hours;
+        if let Some(remaining) = second_of_day.and_then(|second| {
+            SCHEDULE
+                .lock(Cell::get)
+                .session_remaining(second, quiet_hours)
+        }) {
+            tracker.keep_active_until(now + remaining as u64 * 1000);
+        }
+        let phase = tracker.phase(now, &config);
+        if ACTIVITY_PHASE.lock(|current| current.replace(phase)) != phase {
+            info!("Activity phase: {}", phase.name());
+        }
+        let Some(next_phase_change) = tracker.next_phase_change(now, &config) else {
+            info!(
+                "No activity for {} seconds, going to deep sleep",
+                current_settings().idle_timeout
+            );
+            break false;
+        };
+        let quiet_hours_start = second_of_day
+            .zip(quiet_hours)
+            .map(|(second, quiet_hours)| now + quiet_hours.until_start(second) as u64 * 1000);
+        let next_check =
+            quiet_hours_start.map_or(next_phase_change, |start| start.min(next_phase_change));
+        match select4(
+            Timer::at(Instant::from_millis(next_check)),
+            ACTIVITY.wait(),
+            LOW_BATTERY.wait(),
+            // Quiet hours and sessions can only be placed in time once the clock is known
+            WALL_CLOCK_SET.wait(),
+        )
+        .await
         {
-            let mut adc1 = adc1_mutex.lock().await;
-            let mut speed_pot_pin = speed_pot_pin_mutex.lock().await;
-            let avg_pin_value: u16 = ((0..NUM_ADC_SAMPLES)
-                .map(|_| nb::block!(adc1.read_oneshot(&mut speed_pot_pin)).unwrap() as u32)
-                .sum::<u32>()
-                / NUM_ADC_SAMPLES as u32)
-                .try_into()
-                .expect("Average of ADC readings is too large to fit into u16");
-            debug!("Average speed pot pin value: {}", avg_pin_value);
-            let max_duty_percent = map_range(
-                avg_pin_value as u32,
-                MIN_ADC_VOLTAGE.into(),
-                MAX_ADC_VOLTAGE.into(),
-                MIN_MOTOR_DUTY_PERCENT.into(),
-                MAX_MOTOR_DUTY_PERCENT.into(),
-            )
-            .unwrap()
-            .try_into()
-            .expect("Max duty percent is too large to fit into u8");
-            debug!("Max duty percent: {}", max_duty_percent);
-            CURRENT_MAX_MOTOR_DUTY_PERCENT.store(max_duty_percent, Ordering::Relaxed);
-
-            if prev_max_duty_percent.abs_diff(max_duty_percent) > 10 {
-                DRASTIC_PARAMETER_CHANGE.store(true, Ordering::Relaxed);
+            Either4::First(()) | Either4::Fourth(()) => {}
+            Either4::Second(()) => tracker.record_activity(Instant::now().as_millis()),
+            Either4::Third(()) => {
+                info!("Battery below the cutoff, going to deep sleep");
+                break true;
             }
+        }
+    };
+
+    // Make sure the motor is left in a defined state before the chip powers down
+    SHUTDOWN_REQUESTED.signal(());
+    let motor_stop_timeout = Duration::from_millis(SHUTDOWN_MOTOR_STOP_TIMEOUT.into());
+    if with_timeout(motor_stop_timeout, MOTOR_STOPPED.wait())
+        .await
+        .is_err()
+    {
+        error!("Motor did not confirm stop before deep sleep");
+    }
+
+    let mut rtc = rtc_mutex.lock().await;
+    // Sleep through quiet hours, then wake up for the next scheduled session unless the
+    // configured timer comes first
+    let second_of_day = local_second_of_day();
+    let quiet_hours = current_settings().quiet_hours;
+    let quiet_hours_remaining = second_of_day
+        .zip(quiet_hours)
+        .and_then(|(second, quiet_hours)| quiet_hours.remaining(second));
+    let next_session = second_of_day.and_then(|second| {
+        SCHEDULE
+            .lock(Cell::get)
+            .until_next_session(second, quiet_hours)
+    });
+    let wake_after = [
+        DEEP_SLEEP_WAKE_SOURCES.timer,
+        next_session,
+        quiet_hours_remaining,
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+    // Waking up on a timer would only drain a flat cell further, the button still works
+    .filter(|_| !low_battery);
+    let timer_wake_source = wake_after.map(|seconds| {
+        info!("Waking up again in {} seconds", seconds);
+        TimerWakeupSource::new(core::time::Duration::from_secs(seconds.into()))
+    });
+    if DEEP_SLEEP_WAKE_SOURCES.button {
+        // A button still held down would wake the chip straight away
+        Input::new(&mut button_pin, Pull::Up).wait_for_high().await;
+    }
+    let wake_pins: &mut [(&mut dyn RtcPinWithResistors, WakeupLevel)] =
+        &mut [(&mut button_pin, WakeupLevel::Low)];
+    let button_wake_source = DEEP_SLEEP_WAKE_SOURCES
+        .button
+        .then(|| RtcioWakeupSource::new(wake_pins));
+
+    let mut wake_sources: Vec<&dyn WakeSource> = Vec::new();
+    if let Some(timer_wake_source) = &timer_wake_source {
+        wake_sources.push(timer_wake_source);
+    }
+    if let Some(button_wake_source) = &button_wake_source {
+        info!("Press the button to wake up");
+        wake_sources.push(button_wake_source);
+    }
+    if let Some(boot_time) = WALL_CLOCK.lock(Cell::get) {
+        let rtc_wall_clock = sntp::rtc_wall_clock(boot_time + uptime_ms(), rtc.get_time_ms());
+        // SAFETY: only read at boot, nothing else runs past this point
+        unsafe { RTC_WALL_CLOCK = rtc_wall_clock };
+    }
+    rtc.sleep_deep(&wake_sources);
+}
 
-            prev_max_duty_percent = max_duty_percent;
+#[embassy_executor::task]
+async fn monitor_battery(adc1_mutex: &'static Adc1Mutex, mut battery_pin: BatteryPin) {
+    let mut ticker = Ticker::every(Duration::from_secs(BATTERY_READ_INTERVAL.into()));
+    let mut monitor = BatteryMonitor::new(BATTERY);
+    loop {
+        if !current_settings().battery_monitoring {
+            // Start afresh once enabled, so earlier readings do not count towards the cutoff
+            monitor = BatteryMonitor::new(BATTERY);
+            BATTERY_VOLTAGE.lock(|voltage| voltage.set(None));
+            ticker.next().await;
+            continue;
+        }
+        let reading = {
+            let mut adc1 = adc1_mutex.lock().await;
+            monitor
+                .update(
+                    &mut AnalogPin {
+                        adc1: &mut adc1,
+                        pin: &mut battery_pin,
+                    },
+                    current_settings().battery_cutoff,
+                )
+                .await
+        };
+        debug!(
+            "Battery: {} mV at the pin -> {:?} mV",
+            reading.pin_voltage, reading.supply_voltage
+        );
+        BATTERY_VOLTAGE.lock(|voltage| voltage.set(reading.supply_voltage));
+        if reading.below_cutoff {
+            LOW_BATTERY.signal(());
         }
         ticker.next().await;
     }
 }
 
 #[embassy_executor::task]
-async fn monitor_duration_pot(
+async fn monitor_knobs(
     adc1_mutex: &'static Adc1Mutex,
-    duration_pot_pin_mutex: &'static AdcPin1MutexForDuration,
+    mut knob_pins: [Box<dyn AdcInput>; KNOBS.len()],
 ) {
     let mut ticker = Ticker::every(Duration::from_millis(POTENTIOMETER_READ_INTERVAL.into()));
-    let mut prev_max_duration = MIN_MOVEMENT_DURATION;
+    let mut monitors = KNOBS.map(PotentiometerMonitor::new);
     loop {
-        debug!("Checking duration pot pin (#1)");
-        {
+        let calibration = CALIBRATION.lock(Cell::get);
+        let curves = KNOB_CURVES.lock(Cell::get);
+        for (knob, (monitor, pin)) in monitors.iter_mut().zip(&mut knob_pins).enumerate() {
+            let name = KNOBS[knob].name;
+            debug!("Checking {} pot", name);
+            monitor.set_voltage_range(knob_voltage_range(&calibration, knob));
+            monitor.set_curve(curves[knob]);
             let mut adc1 = adc1_mutex.lock().await;
-            let mut duration_pot_pin = duration_pot_pin_mutex.lock().await;
-            let avg_pin_value: u16 = ((0..NUM_ADC_SAMPLES)
-                .map(|_| nb::block!(adc1.read_oneshot(&mut duration_pot_pin)).unwrap() as u32)
-                .sum::<u32>()
-                / NUM_ADC_SAMPLES as u32)
-                .try_into()
-                .expect("Average of ADC readings is too large to fit into u16");
-            debug!("Average duration pot pin value: {}", avg_pin_value);
-            let max_duration = map_range(
-                avg_pin_value as u32,
-                MIN_ADC_VOLTAGE.into(),
-                MAX_ADC_VOLTAGE.into(),
-                MIN_MOVEMENT_DURATION.into(),
-                MAX_MOVEMENT_DURATION.into(),
-            )
-            .unwrap()
-            .try_into()
-            .expect("Max duration is too large to fit into u16");
-            debug!("Max duration: {}", max_duration);
-            CURRENT_MAX_MOVEMENT_DURATION.store(max_duration, Ordering::Relaxed);
-
-            if prev_max_duration.abs_diff(max_duration) > 10 {
-                DRASTIC_PARAMETER_CHANGE.store(true, Ordering::Relaxed);
-            }
-
-            prev_max_duration = max_duration;
+            let reading = monitor
+                .update(&mut AnalogPin {
+                    adc1: &mut adc1,
+                    pin: pin.as_mut(),
+                })
+                .await;
+            debug!("{} pot: {} mV -> {}", name, reading.voltage, reading.value);
+            CALIBRATION_RECORDER.lock(|recorder| {
+                if let Some(recorder) = recorder.borrow_mut().as_mut() {
+                    recorder.record(knob, reading.raw_voltage);
+                }
+            });
         }
         ticker.next().await;
     }
 }
 
+#[embassy_executor::task]
+async fn calibrate_potentiometers(mut flash: FlashStorage) {
+    loop {
+        CALIBRATION_REQUESTED.wait().await;
+        info!(
+            "Calibrating knobs, turn each one to both end stops within {} ms",
+            CALIBRATION_DURATION
+        );
+        CALIBRATION_RECORDER
+            .lock(|recorder| recorder.replace(Some(CalibrationRecorder::new(KNOBS.len()))));
+        CALIBRATING.store(true, Ordering::Relaxed);
+        Timer::after(Duration::from_millis(CALIBRATION_DURATION.into())).await;
+        let recorder = CALIBRATION_RECORDER.lock(|recorder| recorder.take());
+        CALIBRATING.store(false, Ordering::Relaxed);
+
+        let Some(recorder) = recorder else {
+            continue;
+        };
+        match recorder.finish(MIN_CALIBRATED_SPAN) {
+            Ok(calibration) => {
+                CALIBRATION.lock(|stored| stored.set(calibration));
+                match flash.write(CALIBRATION_FLASH_OFFSET, &calibration.to_bytes()) {
+                    Ok(()) => info!("Knob calibration saved"),
+                    Err(e) => error!("Failed to save knob calibration: {:?}", e),
+                }
+            }
+            Err(CalibrationError::KnobNotSwept(knob)) => error!(
+                "Calibration discarded: the {} knob was not swept across its range",
+                KNOBS[knob].name
+            ),
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn start_web_server(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     let mut rx_buffer = [0; BUFFER_SIZE];
@@ -392,41 +1256,319 @@ async fn start_web_server(stack: &'static Stack<WifiDevice<'static, WifiStaDevic
 
         let request = from_utf8(&buf[..n]).unwrap_or("");
         info!("Request: {}", request);
+        ACTIVITY.signal(());
 
         let mut response = {
             if request.starts_with("GET / ") {
-                let state = TEST.load(Ordering::Relaxed);
+                let state = current_settings().test;
                 let html_template = include_str!("index.html");
                 let html_value = html_template.replace("{state}", &state.to_string());
-                format!(
-                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
-                    html_value.len(),
-                    html_value
-                )
+                http_response("200 OK", "text/html", &html_value)
             } else if request.starts_with("POST /toggle") {
-                let new_state = !TEST.load(Ordering::Relaxed);
-                TEST.store(new_state, Ordering::Relaxed);
-                info!("Toggle state changed: {}", new_state);
-                let body = CurrentState::get_json_str();
-                format!(
-                    "HTTP/2.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-                    body.len(),
-                    body
+                match update_settings(|settings| settings.test = !settings.test) {
+                    Ok(settings) => info!("Toggle state changed: {}", settings.test),
+                    Err(e) => error!("Toggle rejected: {}", e),
+                }
+                state_response()
+            } else if let Some(quiet_hours) = active_quiet_hours().filter(|_| {
+                MOTION_START_ROUTES
+                    .iter()
+                    .any(|route| request.starts_with(route))
+            }) {
+                text_response(
+                    "409 Conflict",
+                    &format!(
+                        "Quiet hours ({}) are in effect, motion cannot be started until they end",
+                        quiet_hours
+                    ),
                 )
+            } else if let Some(path) = request.strip_prefix("POST /pattern/") {
+                let name = path.split(' ').next().unwrap_or("");
+                match PatternKind::from_name(name) {
+                    Some(pattern) => {
+                        CURRENT_PATTERN.store(pattern as u8, Ordering::Relaxed);
+                        PATTERN_SELECTED.signal(pattern);
+                        MOTION_INTERRUPTED.signal(());
+                        info!("Pattern selected: {}", pattern.name());
+                        state_response()
+                    }
+                    None => text_response("400 Bad Request", "Unknown pattern"),
+                }
+            } else if request.starts_with("POST /script") {
+                let source = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+                match Script::parse(source) {
+                    Ok(script) => {
+                        SCRIPT_UPLOADED.signal(script);
+                        MOTION_INTERRUPTED.signal(());
+                        info!("Script uploaded");
+                        state_response()
+                    }
+                    Err(e) => text_response("400 Bad Request", &format!("Invalid script: {}", e)),
+                }
+            } else if request.starts_with("POST /recording/start") {
+                RECORDING_COMMAND.signal(RecordingCommand::Start);
+                MOTION_INTERRUPTED.signal(());
+                state_response()
+            } else if request.starts_with("POST /recording/stop") {
+                RECORDING_COMMAND.signal(RecordingCommand::Stop);
+                MOTION_INTERRUPTED.signal(());
+                state_response()
+            } else if request.starts_with("GET /recording") {
+                match LAST_RECORDING.lock().await.as_ref() {
+                    Some(recording) => text_response("200 OK", &recording.to_hex()),
+                    None => text_response("404 Not Found", "No recording"),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /replay") {
+                // Optional query: ?stretch=<permille>&mirror=<0|1>, optional body: hex recording
+                let time_stretch_permille = match query_param(path, "stretch") {
+                    Some(value) => value.parse().ok().filter(|&stretch| stretch > 0),
+                    None => Some(DEFAULT_REPLAY_TIME_STRETCH),
+                };
+                let mirrored = query_param(path, "mirror") == Some("1");
+                let hex = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+                let uploaded = if hex.trim().is_empty() {
+                    Ok(None)
+                } else {
+                    Recording::from_hex(hex).map(Some)
+                };
+
+                match (time_stretch_permille, uploaded) {
+                    (Some(time_stretch_permille), Ok(uploaded)) => {
+                        if let Some(recording) = uploaded {
+                            *LAST_RECORDING.lock().await = Some(recording);
+                        }
+                        RECORDING_COMMAND.signal(RecordingCommand::Replay {
+                            time_stretch_permille,
+                            mirrored,
+                        });
+                        MOTION_INTERRUPTED.signal(());
+                        state_response()
+                    }
+                    (None, _) => text_response("400 Bad Request", "Invalid time stretch"),
+                    (_, Err(e)) => {
+                        text_response("400 Bad Request", &format!("Invalid recording: {}", e))
+                    }
+                }
+            } else if let Some(path) = request.strip_prefix("POST /range/speed") {
+                // Query: ?min=<percent>&max=<percent>, an omitted bound is left unchanged
+                let bound = |key| {
+                    query_param(path, key)
+                        .map(|value| match value.parse() {
+                            Ok(percent) if percent <= 100 => Ok(Duty::from_percent(percent)),
+                            _ => Err(()),
+                        })
+                        .transpose()
+                };
+                let result = match (bound("min"), bound("max")) {
+                    (Ok(min), Ok(max)) => update_settings(|settings| {
+                        settings.min_motor_duty = min.unwrap_or(settings.min_motor_duty);
+                        if let Some(max) = max {
+                            // The knob only controls the upper bound
+                            settings.max_motor_duty = max;
+                            settings.sources[Parameter::Speed as usize] = ParameterSource::Remote;
+                        }
+                    })
+                    .map_err(|e| format!("Invalid range: {}", e)),
+                    _ => Err("Speed bounds must be whole percentages up to 100".to_string()),
+                };
+                match result {
+                    Ok(settings) => {
+                        info!(
+                            "Speed range set to {} - {}",
+                            settings.min_motor_duty, settings.max_motor_duty
+                        );
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /range/duration") {
+                // Query: ?min=<ms>&max=<ms>, an omitted bound is left unchanged
+                let bound = |key| query_param(path, key).map(str::parse).transpose();
+                let result = match (bound("min"), bound("max")) {
+                    (Ok(min), Ok(max)) => update_settings(|settings| {
+                        settings.min_movement_duration =
+                            min.unwrap_or(settings.min_movement_duration);
+                        if let Some(max) = max {
+                            // The knob only controls the upper bound
+                            settings.max_movement_duration = max;
+                            settings.sources[Parameter::Duration as usize] =
+                                ParameterSource::Remote;
+                        }
+                    })
+                    .map_err(|e| format!("Invalid range: {}", e)),
+                    _ => Err("Duration bounds must be whole milliseconds".to_string()),
+                };
+                match result {
+                    Ok(settings) => {
+                        info!(
+                            "Duration range set to {} - {} ms",
+                            settings.min_movement_duration, settings.max_movement_duration
+                        );
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /arbitration/") {
+                let name = path.split(' ').next().unwrap_or("");
+                match Arbitration::from_name(name).map(|arbitration| {
+                    update_settings(|settings| settings.arbitration = arbitration)
+                }) {
+                    Some(Ok(settings)) => {
+                        info!(
+                            "Parameter arbitration set to {}",
+                            settings.arbitration.name()
+                        );
+                        state_response()
+                    }
+                    Some(Err(e)) => text_response(
+                        "400 Bad Request",
+                        &format!("Arbitration not changed: {}", e),
+                    ),
+                    None => text_response("400 Bad Request", "Unknown arbitration policy"),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /idle") {
+                // Query: ?timeout=<s>, saved to flash so it survives deep sleep
+                let result = match query_param(path, "timeout").map(str::parse) {
+                    Some(Ok(timeout)) => {
+                        update_settings(|settings| settings.idle_timeout = timeout)
+                            .map_err(|e| format!("Invalid idle timeout: {}", e))
+                    }
+                    _ => Err("The idle timeout must be given in whole seconds".to_string()),
+                };
+                match result {
+                    Ok(settings) => {
+                        info!("Idle timeout set to {} s", settings.idle_timeout);
+                        save_settings(&settings);
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /light_sleep/") {
+                // `on` or `off`, saved to flash so it survives deep sleep
+                let result = match path.split(' ').next().unwrap_or("") {
+                    "on" => Ok(true),
+                    "off" => Ok(false),
+                    _ => Err("Light sleep must be `on` or `off`".to_string()),
+                }
+                .and_then(|enabled| {
+                    update_settings(|settings| settings.light_sleep = enabled)
+                        .map_err(|e| format!("Light sleep rejected: {}", e))
+                });
+                match result {
+                    Ok(settings) => {
+                        info!("Light sleep during pauses: {}", settings.light_sleep);
+                        save_settings(&settings);
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /battery_monitoring/") {
+                // `on` or `off`, saved to flash so it survives deep sleep
+                let result = match path.split(' ').next().unwrap_or("") {
+                    "on" => Ok(true),
+                    "off" => Ok(false),
+                    _ => Err("Battery monitoring must be `on` or `off`".to_string()),
+                }
+                .and_then(|enabled| {
+                    update_settings(|settings| settings.battery_monitoring = enabled)
+                        .map_err(|e| format!("Battery monitoring rejected: {}", e))
+                });
+                match result {
+                    Ok(settings) => {
+                        info!("Battery monitoring: {}", settings.battery_monitoring);
+                        save_settings(&settings);
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /battery") {
+                // Query: ?cutoff=<mV>
+                let result = match query_param(path, "cutoff").map(str::parse) {
+                    Some(Ok(cutoff)) => {
+                        update_settings(|settings| settings.battery_cutoff = cutoff)
+                            .map_err(|e| format!("Invalid battery cutoff: {}", e))
+                    }
+                    _ => Err("The battery cutoff must be given in whole mV".to_string()),
+                };
+                match result {
+                    Ok(settings) => {
+                        info!("Battery cutoff set to {} mV", settings.battery_cutoff);
+                        state_response()
+                    }
+                    Err(message) => text_response("400 Bad Request", &message),
+                }
+            } else if request.starts_with("POST /calibration") {
+                CALIBRATION_REQUESTED.signal(());
+                info!("Calibration requested");
+                state_response()
+            } else if let Some(path) = request.strip_prefix("POST /curve/") {
+                let (knob_name, curve_name) = path
+                    .split(' ')
+                    .next()
+                    .and_then(|path| path.split_once('/'))
+                    .unwrap_or(("", ""));
+                let knob = KNOBS.iter().position(|knob| knob.name == knob_name);
+                match (knob, Curve::from_name(curve_name)) {
+                    (Some(knob), Some(curve)) => {
+                        KNOB_CURVES.lock(|curves| {
+                            let mut updated = curves.get();
+                            updated[knob] = curve;
+                            curves.set(updated);
+                        });
+                        info!("{} knob curve set to {}", KNOBS[knob].name, curve.name());
+                        state_response()
+                    }
+                    _ => text_response("400 Bad Request", "Unknown knob or curve"),
+                }
+            } else if let Some(path) = request.strip_prefix("POST /quiet_hours/") {
+                // `HH:MM-HH:MM` in local time, or `off`. Saved to flash so the window survives
+                // deep sleep
+                let window = path.split(' ').next().unwrap_or("");
+                let quiet_hours = match window {
+                    "off" => Some(None),
+                    window => QuietHours::parse(window).map(Some),
+                };
+                match quiet_hours.map(|quiet_hours| {
+                    update_settings(|settings| settings.quiet_hours = quiet_hours)
+                }) {
+                    Some(Ok(settings)) => {
+                        match settings.quiet_hours {
+                            Some(quiet_hours) => info!("Quiet hours set to {}", quiet_hours),
+                            None => info!("Quiet hours turned off"),
+                        }
+                        save_settings(&settings);
+                        state_response()
+                    }
+                    Some(Err(e)) => {
+                        text_response("400 Bad Request", &format!("Invalid quiet hours: {}", e))
+                    }
+                    None => text_response(
+                        "400 Bad Request",
+                        "Quiet hours must be `HH:MM-HH:MM` or `off`",
+                    ),
+                }
+            } else if request.starts_with("POST /schedule") {
+                let source = request.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+                match Schedule::parse(source) {
+                    Ok(schedule) => {
+                        SCHEDULE.lock(|stored| stored.set(schedule));
+                        match FlashStorage::new().write(SCHEDULE_FLASH_OFFSET, &schedule.to_bytes())
+                        {
+                            Ok(()) => {
+                                info!("Schedule saved with {} sessions", schedule.slots().len())
+                            }
+                            Err(e) => error!("Failed to save the schedule: {:?}", e),
+                        }
+                        state_response()
+                    }
+                    Err(e) => text_response("400 Bad Request", &format!("Invalid schedule: {}", e)),
+                }
+            } else if request.starts_with("GET /schedule") {
+                text_response("200 OK", &SCHEDULE.lock(Cell::get).to_string())
             } else if request.starts_with("GET /state") {
-                let body = CurrentState::get_json_str();
-                format!(
-                    "HTTP/2.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-                    body.len(),
-                    body
-                )
+                state_response()
             } else {
-                let message = "Not Found";
-                format!(
-                    "HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{}",
-                    message.len(),
-                    message
-                )
+                text_response("404 Not Found", "Not Found")
             }
         };
 
@@ -488,6 +1630,84 @@ async fn connection(mut controller: WifiController<'static>) {
     }
 }
 
+#[embassy_executor::task]
+async fn sync_wall_clock(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; sntp::PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; sntp::PACKET_LEN];
+
+    stack.wait_config_up().await;
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(SNTP_LOCAL_PORT).unwrap();
+    loop {
+        let next_sync = match query_wall_clock(stack, &socket).await {
+            Some(unix_time) => {
+                WALL_CLOCK.lock(|clock| clock.set(Some(unix_time - uptime_ms())));
+                WALL_CLOCK_SET.signal(());
+                info!(
+                    "Wall clock set to {} s since the Unix epoch",
+                    unix_time / 1000
+                );
+                WALL_CLOCK_SYNC_INTERVAL
+            }
+            None => SNTP_RETRY_INTERVAL,
+        };
+        Timer::after(Duration::from_secs(next_sync.into())).await;
+    }
+}
+
+/// Unix time in ms according to the NTP server, failures are logged
+async fn query_wall_clock(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    socket: &UdpSocket<'_>,
+) -> Option<u64> {
+    let server = match stack.dns_query(NTP_SERVER, DnsQueryType::A).await {
+        Ok(addresses) => *addresses.first()?,
+        Err(e) => {
+            error!("Failed to resolve {}: {:?}", NTP_SERVER, e);
+            return None;
+        }
+    };
+    if let Err(e) = socket
+        .send_to(&sntp::request(), (server, sntp::NTP_PORT))
+        .await
+    {
+        error!("Failed to send SNTP request: {:?}", e);
+        return None;
+    }
+
+    let mut reply = [0; sntp::PACKET_LEN];
+    match with_timeout(
+        Duration::from_millis(SNTP_TIMEOUT.into()),
+        socket.recv_from(&mut reply),
+    )
+    .await
+    {
+        Ok(Ok((len, _))) => match sntp::parse_reply(&reply[..len]) {
+            Ok(unix_time) => Some(unix_time),
+            Err(e) => {
+                error!("Invalid SNTP reply: {}", e);
+                None
+            }
+        },
+        Ok(Err(e)) => {
+            error!("SNTP receive error: {:?}", e);
+            None
+        }
+        Err(_) => {
+            error!("No SNTP reply within {} ms", SNTP_TIMEOUT);
+            None
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     stack.run().await
@@ -5,36 +5,308 @@ pub enum MapRangeError {
     DivisionByZero,
 }
 
-pub fn map_range<T>(
+/// How results that fall between two integers are resolved. Floats are never rounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    #[default]
+    Floor,
+    Nearest, // Halves round up
+    Ceil,
+}
+
+impl Rounding {
+    /// Integer division for a positive `denominator`
+    fn divide(self, numerator: i128, denominator: i128) -> i128 {
+        match self {
+            Rounding::Floor => numerator.div_euclid(denominator),
+            Rounding::Nearest => (2 * numerator + denominator).div_euclid(2 * denominator),
+            Rounding::Ceil => -(-numerator).div_euclid(denominator),
+        }
+    }
+}
+
+/// Numbers that can be mapped between ranges: integers use exact fixed-point arithmetic, floats
+/// plain floating-point arithmetic
+pub trait MapValue: Copy + PartialOrd {
+    /// `out_min + (value - in_min) * (out_max - out_min) / (in_max - in_min)`, extrapolating past
+    /// the input range and saturating at the limits of the type. `in_min` and `in_max` must differ
+    fn interpolate(
+        value: Self,
+        in_min: Self,
+        in_max: Self,
+        out_min: Self,
+        out_max: Self,
+        rounding: Rounding,
+    ) -> Self;
+}
+
+macro_rules! impl_map_value_for_integer {
+    ($($integer:ty),*) => {$(
+        impl MapValue for $integer {
+            fn interpolate(
+                value: Self,
+                in_min: Self,
+                in_max: Self,
+                out_min: Self,
+                out_max: Self,
+                rounding: Rounding,
+            ) -> Self {
+                // Wide enough that neither the differences nor their product can overflow
+                let mut numerator =
+                    (value as i128 - in_min as i128) * (out_max as i128 - out_min as i128);
+                let mut denominator = in_max as i128 - in_min as i128;
+                if denominator < 0 {
+                    numerator = -numerator;
+                    denominator = -denominator;
+                }
+                let mapped = out_min as i128 + rounding.divide(numerator, denominator);
+                mapped.clamp(Self::MIN as i128, Self::MAX as i128) as Self
+            }
+        }
+    )*};
+}
+
+impl_map_value_for_integer!(u8, u16, u32, i8, i16, i32);
+
+impl MapValue for f32 {
+    fn interpolate(
+        value: Self,
+        in_min: Self,
+        in_max: Self,
+        out_min: Self,
+        out_max: Self,
+        _rounding: Rounding,
+    ) -> Self {
+        out_min + (value - in_min) * (out_max - out_min) / (in_max - in_min)
+    }
+}
+
+/// Maps a value from the input range onto the output range, rounding down. Either range may be
+/// descending; values outside of the input range are rejected
+pub fn map_range<T: MapValue>(
     in_value: T,
     in_min: T,
     in_max: T,
     out_min: T,
     out_max: T,
-) -> Result<T, MapRangeError>
-where
-    T: num_traits::CheckedSub
-        + num_traits::CheckedAdd
-        + num_traits::CheckedMul
-        + num_traits::CheckedDiv,
-{
-    // ((in_value - in_min) * (out_max - out_min) / (in_max - in_min)) + out_min
-    let in_range = in_max
-        .checked_sub(&in_min)
-        .ok_or(MapRangeError::Underflow)?;
-    let out_range = out_max
-        .checked_sub(&out_min)
-        .ok_or(MapRangeError::Underflow)?;
-    let scaled_value = in_value
-        .checked_sub(&in_min)
-        .ok_or(MapRangeError::Underflow)?
-        .checked_mul(&out_range)
-        .ok_or(MapRangeError::Overflow)?;
-    let mapped_value = scaled_value
-        .checked_div(&in_range)
-        .ok_or(MapRangeError::DivisionByZero)?
-        .checked_add(&out_min)
-        .ok_or(MapRangeError::Overflow)?;
-
-    Ok(mapped_value)
+) -> Result<T, MapRangeError> {
+    let (low, high) = ordered(in_min, in_max)?;
+    if in_value > high {
+        return Err(MapRangeError::Overflow);
+    }
+    if !(low..=high).contains(&in_value) {
+        return Err(MapRangeError::Underflow);
+    }
+    Ok(T::interpolate(
+        in_value,
+        in_min,
+        in_max,
+        out_min,
+        out_max,
+        Rounding::Floor,
+    ))
+}
+
+/// Like `map_range`, but values outside of the input range are clamped to its ends so the result
+/// always lies within the output range
+pub fn map_range_clamped<T: MapValue>(
+    in_value: T,
+    in_min: T,
+    in_max: T,
+    out_min: T,
+    out_max: T,
+    rounding: Rounding,
+) -> Result<T, MapRangeError> {
+    let (low, high) = ordered(in_min, in_max)?;
+    let in_value = if in_value < low {
+        low
+    } else if in_value > high {
+        high
+    } else {
+        in_value
+    };
+    Ok(T::interpolate(
+        in_value, in_min, in_max, out_min, out_max, rounding,
+    ))
+}
+
+/// Like `map_range`, but values outside of the input range are extrapolated, saturating at the
+/// limits of the type instead of failing
+pub fn map_range_saturating<T: MapValue>(
+    in_value: T,
+    in_min: T,
+    in_max: T,
+    out_min: T,
+    out_max: T,
+    rounding: Rounding,
+) -> Result<T, MapRangeError> {
+    ordered(in_min, in_max)?;
+    Ok(T::interpolate(
+        in_value, in_min, in_max, out_min, out_max, rounding,
+    ))
+}
+
+fn ordered<T: MapValue>(a: T, b: T) -> Result<(T, T), MapRangeError> {
+    if a < b {
+        Ok((a, b))
+    } else if b < a {
+        Ok((b, a))
+    } else {
+        // Equal, or not comparable at all such as NaN
+        Err(MapRangeError::DivisionByZero)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Distinct ends of a range, in either order
+    fn range() -> impl Strategy<Value = (u16, u16)> {
+        (any::<u16>(), any::<u16>()).prop_filter("ends must differ", |(a, b)| a != b)
+    }
+
+    /// Exact quotient, which f64 represents well enough for u16 operands to round correctly
+    fn exact(value: u16, in_min: u16, in_max: u16, out_min: u16, out_max: u16) -> f64 {
+        out_min as f64
+            + (value as f64 - in_min as f64) * (out_max as f64 - out_min as f64)
+                / (in_max as f64 - in_min as f64)
+    }
+
+    #[test]
+    fn maps_the_ends_and_the_middle() {
+        assert_eq!(map_range(0u16, 0, 100, 0, 1000), Ok(0));
+        assert_eq!(map_range(50u16, 0, 100, 0, 1000), Ok(500));
+        assert_eq!(map_range(100u16, 0, 100, 0, 1000), Ok(1000));
+        assert_eq!(map_range(25u16, 100, 0, 0, 1000), Ok(750));
+        assert_eq!(map_range(25.0f32, 0.0, 100.0, 1.0, 0.0), Ok(0.75));
+    }
+
+    #[test]
+    fn equal_or_incomparable_ends_are_rejected() {
+        assert_eq!(
+            map_range(5u8, 5, 5, 0, 10),
+            Err(MapRangeError::DivisionByZero)
+        );
+        assert_eq!(
+            map_range(0.5f32, f32::NAN, 1.0, 0.0, 1.0),
+            Err(MapRangeError::DivisionByZero)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn ends_map_onto_ends((in_min, in_max) in range(), out_min: u16, out_max: u16) {
+            prop_assert_eq!(map_range(in_min, in_min, in_max, out_min, out_max), Ok(out_min));
+            prop_assert_eq!(map_range(in_max, in_min, in_max, out_min, out_max), Ok(out_max));
+        }
+
+        #[test]
+        fn results_stay_within_the_output_range(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let value = value.clamp(in_min.min(in_max), in_min.max(in_max));
+            let mapped = map_range(value, in_min, in_max, out_min, out_max).unwrap();
+            prop_assert!((out_min.min(out_max)..=out_min.max(out_max)).contains(&mapped));
+        }
+
+        #[test]
+        fn ascending_output_never_decreases(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            a: u16,
+            b: u16,
+        ) {
+            let (in_min, in_max) = (in_min.min(in_max), in_min.max(in_max));
+            let (out_min, out_max) = (out_min.min(out_max), out_min.max(out_max));
+            let (a, b) = (a.clamp(in_min, in_max), b.clamp(in_min, in_max));
+            let (low, high) = (a.min(b), a.max(b));
+            prop_assert!(
+                map_range(low, in_min, in_max, out_min, out_max).unwrap()
+                    <= map_range(high, in_min, in_max, out_min, out_max).unwrap()
+            );
+        }
+
+        #[test]
+        fn descending_input_range_mirrors_the_output_range(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let value = value.clamp(in_min.min(in_max), in_min.max(in_max));
+            prop_assert_eq!(
+                map_range(value, in_max, in_min, out_min, out_max),
+                map_range(value, in_min, in_max, out_max, out_min)
+            );
+        }
+
+        #[test]
+        fn rounding_matches_the_exact_quotient(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let value = value.clamp(in_min.min(in_max), in_min.max(in_max));
+            let exact = exact(value, in_min, in_max, out_min, out_max);
+            let map = |rounding| {
+                map_range_clamped(value, in_min, in_max, out_min, out_max, rounding).unwrap()
+            };
+            prop_assert_eq!(map(Rounding::Floor) as f64, exact.floor());
+            prop_assert_eq!(map(Rounding::Ceil) as f64, exact.ceil());
+            prop_assert_eq!(map(Rounding::Nearest) as f64, (exact + 0.5).floor());
+        }
+
+        #[test]
+        fn values_past_the_input_range_overflow_or_underflow(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let (low, high) = (in_min.min(in_max), in_min.max(in_max));
+            let mapped = map_range(value, in_min, in_max, out_min, out_max);
+            if value > high {
+                prop_assert_eq!(mapped, Err(MapRangeError::Overflow));
+            } else if value < low {
+                prop_assert_eq!(mapped, Err(MapRangeError::Underflow));
+            } else {
+                prop_assert!(mapped.is_ok());
+            }
+        }
+
+        #[test]
+        fn clamped_values_map_like_the_nearest_end(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let clamped = value.clamp(in_min.min(in_max), in_min.max(in_max));
+            prop_assert_eq!(
+                map_range_clamped(value, in_min, in_max, out_min, out_max, Rounding::Floor),
+                map_range(clamped, in_min, in_max, out_min, out_max)
+            );
+        }
+
+        #[test]
+        fn extrapolation_saturates_at_the_type_limits(
+            (in_min, in_max) in range(),
+            out_min: u16,
+            out_max: u16,
+            value: u16,
+        ) {
+            let mapped =
+                map_range_saturating(value, in_min, in_max, out_min, out_max, Rounding::Floor)
+                    .unwrap();
+            let exact = exact(value, in_min, in_max, out_min, out_max);
+            prop_assert_eq!(mapped as f64, exact.floor().clamp(0.0, u16::MAX as f64));
+        }
+    }
 }
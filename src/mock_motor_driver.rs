@@ -0,0 +1,61 @@
+use crate::duty::Duty;
+use crate::motion::MotorDirection;
+use crate::motor_driver::{MotorCommand, MotorDriver};
+use alloc::vec::Vec;
+use embassy_time::Duration;
+
+/// Motor driver for host tests that records every command along with the time it was issued
+#[derive(Debug, Default)]
+pub struct MockMotorDriver {
+    now_ms: u64,
+    log: Vec<(u64, MotorCommand)>,
+}
+
+impl MockMotorDriver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&mut self, duration_ms: u64) {
+        self.now_ms += duration_ms;
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    pub fn log(&self) -> &[(u64, MotorCommand)] {
+        &self.log
+    }
+
+    pub fn last_command(&self) -> Option<MotorCommand> {
+        self.log.last().map(|(_, command)| *command)
+    }
+
+    fn record(&mut self, command: MotorCommand) {
+        self.log.push((self.now_ms, command));
+    }
+}
+
+impl MotorDriver for MockMotorDriver {
+    fn start_movement(&mut self, direction: MotorDirection, duty: Duty) {
+        self.record(MotorCommand::StartMovement { direction, duty });
+    }
+
+    fn set_duty(&mut self, direction: MotorDirection, duty: Duty) {
+        self.record(MotorCommand::SetDuty { direction, duty });
+    }
+
+    fn stop_coast(&mut self) {
+        self.record(MotorCommand::StopCoast);
+    }
+
+    fn brake(&mut self) {
+        self.record(MotorCommand::Brake);
+    }
+
+    async fn hold(&mut self, duration: Duration) {
+        // Advance the simulated clock instead of waiting on the real one
+        self.advance(duration.as_millis());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::duty::Duty;
+use crate::pattern::{MotionPattern, Pattern, PatternKind};
+use crate::ramp::{Ramp, RampProfile};
+use crate::recording::Replay;
+use crate::script::{Script, ScriptRunner};
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorDirection {
+    Forward,
+    Reverse,
+}
+
+impl MotorDirection {
+    pub fn reversed(self) -> Self {
+        match self {
+            MotorDirection::Forward => MotorDirection::Reverse,
+            MotorDirection::Reverse => MotorDirection::Forward,
+        }
+    }
+}
+
+/// Snapshot of the runtime parameters that movements are generated from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotionParameters {
+    pub min_duty: Duty,
+    pub max_duty: Duty,
+    pub min_movement_duration: u16, // ms
+    pub max_movement_duration: u16, // ms
+    pub ramp_profile: RampProfile,
+    pub max_ramp_duration: u16,        // ms
+    pub pause_probability_percent: u8, // Chance of pausing after a movement
+    pub min_pause_duration: u16,       // ms
+    pub max_pause_duration: u16,       // ms
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionStep {
+    Move {
+        direction: MotorDirection,
+        duty: Duty,
+        duration_ms: u16,
+        ramp: Ramp,
+    },
+    /// Motor held stopped
+    Pause { duration_ms: u16 },
+}
+
+impl MotionStep {
+    pub fn duration_ms(&self) -> u16 {
+        match self {
+            MotionStep::Move { duration_ms, .. } | MotionStep::Pause { duration_ms } => {
+                *duration_ms
+            }
+        }
+    }
+}
+
+/// Generates movements from the selected play pattern, script or replay, independent of the motor
+/// hardware
+pub struct MotionEngine<R: Rng> {
+    rng: R,
+    pattern: Pattern,
+    script: Option<ScriptRunner>,
+    replay: Option<Replay>,
+    quiet: bool,
+    previous_step_was_pause: bool,
+}
+
+impl<R: Rng> MotionEngine<R> {
+    pub fn new(rng: R, pattern_kind: PatternKind) -> Self {
+        Self {
+            rng,
+            pattern: Pattern::new(pattern_kind),
+            script: None,
+            replay: None,
+            quiet: false,
+            previous_step_was_pause: false,
+        }
+    }
+
+    /// Switches to a pattern from its initial state, ending any running script or replay
+    pub fn set_pattern(&mut self, pattern_kind: PatternKind) {
+        self.pattern = Pattern::new(pattern_kind);
+        self.script = None;
+        self.replay = None;
+    }
+
+    /// Plays the script from its start in place of the selected pattern
+    pub fn run_script(&mut self, script: Script) {
+        self.script = Some(ScriptRunner::new(script));
+        self.replay = None;
+    }
+
+    pub fn is_running_script(&self) -> bool {
+        self.script.is_some()
+    }
+
+    /// Plays a recording from its start in place of the selected pattern
+    pub fn run_replay(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+        self.script = None;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Holds the motor stopped while quiet, whatever was playing carries on afterwards
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Next step of the running script or replay, or of the selected pattern with random pauses
+    /// sprinkled in between movements
+    pub fn next_step(&mut self, parameters: &MotionParameters) -> MotionStep {
+        if self.quiet {
+            return MotionStep::Pause {
+                duration_ms: parameters
+                    .max_pause_duration
+                    .max(parameters.min_pause_duration),
+            };
+        }
+        // Scripts and recordings spell out their own pauses
+        if let Some(script) = &mut self.script {
+            return script.next_step(&mut self.rng, parameters);
+        }
+        if let Some(replay) = &mut self.replay {
+            return replay.next_step(&mut self.rng, parameters);
+        }
+
+        let pause_probability_percent = parameters.pause_probability_percent.min(100);
+        let step = if !self.previous_step_was_pause
+            && self.rng.gen_ratio(pause_probability_percent.into(), 100)
+        {
+            let max_pause_duration = parameters
+                .max_pause_duration
+                .max(parameters.min_pause_duration);
+            MotionStep::Pause {
+                duration_ms: self
+                    .rng
+                    .gen_range(parameters.min_pause_duration..=max_pause_duration),
+            }
+        } else {
+            self.pattern.next_step(&mut self.rng, parameters)
+        };
+
+        self.previous_step_was_pause = matches!(step, MotionStep::Pause { .. });
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_motor_driver::MockMotorDriver;
+    use crate::motor_driver::{MotorCommand, MotorDriver};
+    use crate::ramp::RampedMotor;
+    use embassy_futures::block_on;
+    use embassy_time::Duration;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const PARAMETERS: MotionParameters = MotionParameters {
+        min_duty: Duty::from_percent(20),
+        max_duty: Duty::from_percent(80),
+        min_movement_duration: 200,
+        max_movement_duration: 2_000,
+        ramp_profile: RampProfile::Linear,
+        max_ramp_duration: 300,
+        pause_probability_percent: 30,
+        min_pause_duration: 500,
+        max_pause_duration: 3_000,
+    };
+
+    fn engine(seed: u64) -> MotionEngine<SmallRng> {
+        MotionEngine::new(SmallRng::seed_from_u64(seed), PatternKind::RandomWalk)
+    }
+
+    #[test]
+    fn same_seed_gives_same_steps() {
+        let mut first = engine(1234);
+        let mut second = engine(1234);
+        for _ in 0..100 {
+            assert_eq!(first.next_step(&PARAMETERS), second.next_step(&PARAMETERS));
+        }
+    }
+
+    #[test]
+    fn steps_stay_within_parameters() {
+        for kind in PatternKind::ALL {
+            let mut engine = MotionEngine::new(SmallRng::seed_from_u64(7), kind);
+            for _ in 0..500 {
+                match engine.next_step(&PARAMETERS) {
+                    MotionStep::Move {
+                        duty, duration_ms, ..
+                    } => {
+                        assert!(duty <= PARAMETERS.max_duty, "{:?}: {}", kind, duty);
+                        assert!(duration_ms > 0, "{:?}", kind);
+                    }
+                    // Hide and peek hides for up to twice the longest pause
+                    MotionStep::Pause { duration_ms } => {
+                        assert!(
+                            duration_ms <= 2 * PARAMETERS.max_pause_duration,
+                            "{:?}",
+                            kind
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_pauses_never_follow_each_other() {
+        let parameters = MotionParameters {
+            pause_probability_percent: 100,
+            ..PARAMETERS
+        };
+        let mut engine = engine(42);
+        let mut previous_was_pause = false;
+        for _ in 0..100 {
+            let is_pause = matches!(engine.next_step(&parameters), MotionStep::Pause { .. });
+            assert!(!(is_pause && previous_was_pause));
+            previous_was_pause = is_pause;
+        }
+    }
+
+    #[test]
+    fn quiet_engine_only_pauses() {
+        let mut engine = engine(0);
+        engine.set_quiet(true);
+        for _ in 0..20 {
+            assert_eq!(
+                engine.next_step(&PARAMETERS),
+                MotionStep::Pause {
+                    duration_ms: PARAMETERS.max_pause_duration
+                }
+            );
+        }
+        engine.set_quiet(false);
+        assert!(!engine.is_quiet());
+    }
+
+    #[test]
+    fn script_replaces_pattern_until_another_is_selected() {
+        let mut engine = engine(0);
+        engine.run_script(Script::parse("fwd 50% 700ms ramp=none").unwrap());
+        assert!(engine.is_running_script());
+        for _ in 0..5 {
+            assert_eq!(
+                engine.next_step(&PARAMETERS),
+                MotionStep::Move {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(50),
+                    duration_ms: 700,
+                    ramp: Ramp::default(),
+                }
+            );
+        }
+        engine.set_pattern(PatternKind::MouseScurry);
+        assert!(!engine.is_running_script());
+    }
+
+    /// Plays steps the way the firmware's main loop does, on the simulated clock of the mock
+    fn play(
+        engine: &mut MotionEngine<SmallRng>,
+        motor: &mut RampedMotor<MockMotorDriver>,
+        steps: usize,
+    ) {
+        for _ in 0..steps {
+            let start = motor.inner_mut().now_ms();
+            let step = engine.next_step(&PARAMETERS);
+            match step {
+                MotionStep::Move {
+                    direction,
+                    duty,
+                    ramp,
+                    ..
+                } => block_on(motor.ramp_to(direction, duty, ramp)),
+                MotionStep::Pause { .. } => {
+                    block_on(motor.brake_then_coast(Duration::from_millis(100)))
+                }
+            }
+            let elapsed = motor.inner_mut().now_ms() - start;
+            let remaining = u64::from(step.duration_ms()).saturating_sub(elapsed);
+            block_on(motor.hold(Duration::from_millis(remaining)));
+        }
+    }
+
+    #[test]
+    fn script_steps_drive_the_motor_on_schedule() {
+        let mut engine = engine(0);
+        engine.run_script(
+            Script::parse("fwd 50% 400ms ramp=linear\npause 300ms\nrev 30% 200ms ramp=none")
+                .unwrap(),
+        );
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        play(&mut engine, &mut motor, 3);
+
+        let motor = motor.inner_mut();
+        assert_eq!(motor.now_ms(), 900);
+        let log = motor.log();
+        // The ramp takes the shorter of the longest ramp and half the movement
+        let ramp_times: Vec<u64> = log[..10].iter().map(|(time, _)| *time).collect();
+        assert_eq!(
+            ramp_times,
+            (1..=10).map(|step| step * 20).collect::<Vec<_>>()
+        );
+        let ramp_duties: Vec<Duty> = log[..10]
+            .iter()
+            .filter_map(|(_, command)| match command {
+                MotorCommand::SetDuty { duty, .. } => Some(*duty),
+                _ => None,
+            })
+            .collect();
+        assert!(ramp_duties.is_sorted() && ramp_duties.len() == 10);
+        assert_eq!(
+            log[9],
+            (
+                200,
+                MotorCommand::SetDuty {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(50),
+                }
+            )
+        );
+        assert_eq!(
+            log[10..],
+            [
+                (400, MotorCommand::Brake),
+                (500, MotorCommand::StopCoast),
+                (
+                    700,
+                    MotorCommand::StartMovement {
+                        direction: MotorDirection::Reverse,
+                        duty: Duty::from_percent(30),
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -1,16 +1,17 @@
+use esp_hal::ledc::channel::ChannelHW;
 use esp_hal::ledc::timer::TimerIFace;
 use esp_hal::{
     gpio::OutputPin,
     ledc::{channel, timer::TimerSpeed, Ledc},
     peripheral::Peripheral,
+    peripherals::GPIO,
     prelude::*,
 };
+use stepper_motor_cat_toy::duty::Duty;
+use stepper_motor_cat_toy::motion::MotorDirection;
+use stepper_motor_cat_toy::motor_driver::MotorDriver;
 
-#[derive(Debug)]
-pub enum MotorDirection {
-    Forward,
-    Reverse,
-}
+const SIMPLE_GPIO_OUTPUT: u8 = 0x80; // Output signal index that detaches a pin from the LEDC
 
 pub struct Motor<'a, S, O1, O2>
 where
@@ -62,22 +63,54 @@ where
             pwm_channel_reverse,
         }
     }
+}
 
-    pub fn start_movement(&mut self, direction: &MotorDirection, duty_percent: u8) {
+impl<S, O1, O2> MotorDriver for Motor<'_, S, O1, O2>
+where
+    S: TimerSpeed,
+    O1: OutputPin,
+    O2: OutputPin,
+{
+    fn start_movement(&mut self, direction: MotorDirection, duty: Duty) {
+        self.set_duty(direction, duty);
+    }
+
+    fn set_duty(&mut self, direction: MotorDirection, duty: Duty) {
+        // Write the raw duty so the full 14-bit timer resolution is kept
         match direction {
             MotorDirection::Forward => {
-                self.pwm_channel_forward.set_duty(duty_percent).unwrap();
-                self.pwm_channel_reverse.set_duty(0).unwrap();
+                self.pwm_channel_forward.set_duty_hw(duty.raw().into());
+                self.pwm_channel_reverse.set_duty_hw(0);
             }
             MotorDirection::Reverse => {
-                self.pwm_channel_forward.set_duty(0).unwrap();
-                self.pwm_channel_reverse.set_duty(duty_percent).unwrap();
+                self.pwm_channel_forward.set_duty_hw(0);
+                self.pwm_channel_reverse.set_duty_hw(duty.raw().into());
             }
         }
     }
 
-    // fn stop(&mut self) {
-    //     self.pwm_channel_forward.set_duty(0).unwrap();
-    //     self.pwm_channel_reverse.set_duty(0).unwrap();
-    // }
+    /// Lets the motor coast by pulling both H-bridge inputs low
+    fn stop_coast(&mut self) {
+        self.pwm_channel_forward.set_duty_hw(0);
+        self.pwm_channel_reverse.set_duty_hw(0);
+    }
+
+    /// Shorts the motor windings by driving both H-bridge inputs high
+    fn brake(&mut self) {
+        self.pwm_channel_forward.set_duty_hw(Duty::MAX.raw().into());
+        self.pwm_channel_reverse.set_duty_hw(Duty::MAX.raw().into());
+    }
+}
+
+/// Pulls the H-bridge inputs low through the GPIO registers, bypassing the `Motor` that owns them,
+/// for when the firmware can no longer be trusted to stop the motor, e.g. while panicking
+pub fn force_inputs_low(pins: &[u8]) {
+    // SAFETY: only called once the firmware has stopped, so nothing else drives these pins anymore
+    let gpio = unsafe { GPIO::steal() };
+    for &pin in pins {
+        gpio.out_w1tc().write(|w| unsafe { w.bits(1 << pin) });
+        gpio.func_out_sel_cfg(pin.into())
+            .write(|w| unsafe { w.out_sel().bits(SIMPLE_GPIO_OUTPUT) });
+        gpio.enable_w1ts().write(|w| unsafe { w.bits(1 << pin) });
+    }
 }
@@ -0,0 +1,40 @@
+use crate::duty::Duty;
+use crate::motion::MotorDirection;
+use embassy_time::{Duration, Timer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorCommand {
+    StartMovement {
+        direction: MotorDirection,
+        duty: Duty,
+    },
+    SetDuty {
+        direction: MotorDirection,
+        duty: Duty,
+    },
+    StopCoast,
+    Brake,
+}
+
+/// Hardware-independent interface to an H-bridge driven DC motor
+pub trait MotorDriver {
+    fn start_movement(&mut self, direction: MotorDirection, duty: Duty);
+    fn set_duty(&mut self, direction: MotorDirection, duty: Duty);
+    /// Releases the motor so it spins down freely
+    fn stop_coast(&mut self);
+    /// Actively stops the motor by shorting its windings
+    fn brake(&mut self);
+
+    /// Keeps the motor in its current state for `duration`
+    async fn hold(&mut self, duration: Duration) {
+        Timer::after(duration).await;
+    }
+
+    /// Brakes for `brake_duration` before releasing the motor to coast, so it stops quickly without
+    /// holding the H-bridge in the braking state indefinitely
+    async fn brake_then_coast(&mut self, brake_duration: Duration) {
+        self.brake();
+        self.hold(brake_duration).await;
+        self.stop_coast();
+    }
+}
@@ -0,0 +1,447 @@
+use crate::duty::Duty;
+use crate::map_range::{map_range_clamped, Rounding};
+use crate::motion::{MotionParameters, MotionStep, MotorDirection};
+use crate::ramp::{Ramp, RampProfile};
+use core::ops::RangeInclusive;
+use rand::Rng;
+
+/// Shared interface of every play pattern: produce the next movement from a parameter snapshot
+pub trait MotionPattern {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum PatternKind {
+    #[default]
+    RandomWalk,
+    MouseScurry,
+    BirdFlutter,
+    SnakeSlither,
+    HideAndPeek,
+}
+
+impl PatternKind {
+    pub const ALL: [PatternKind; 5] = [
+        PatternKind::RandomWalk,
+        PatternKind::MouseScurry,
+        PatternKind::BirdFlutter,
+        PatternKind::SnakeSlither,
+        PatternKind::HideAndPeek,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PatternKind::RandomWalk => "random_walk",
+            PatternKind::MouseScurry => "mouse_scurry",
+            PatternKind::BirdFlutter => "bird_flutter",
+            PatternKind::SnakeSlither => "snake_slither",
+            PatternKind::HideAndPeek => "hide_and_peek",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+/// State of the currently selected pattern
+pub enum Pattern {
+    RandomWalk(RandomWalk),
+    MouseScurry(MouseScurry),
+    BirdFlutter(BirdFlutter),
+    SnakeSlither(SnakeSlither),
+    HideAndPeek(HideAndPeek),
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind) -> Self {
+        match kind {
+            PatternKind::RandomWalk => Pattern::RandomWalk(RandomWalk::default()),
+            PatternKind::MouseScurry => Pattern::MouseScurry(MouseScurry::default()),
+            PatternKind::BirdFlutter => Pattern::BirdFlutter(BirdFlutter::default()),
+            PatternKind::SnakeSlither => Pattern::SnakeSlither(SnakeSlither::default()),
+            PatternKind::HideAndPeek => Pattern::HideAndPeek(HideAndPeek::default()),
+        }
+    }
+}
+
+impl MotionPattern for Pattern {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        match self {
+            Pattern::RandomWalk(pattern) => pattern.next_step(rng, parameters),
+            Pattern::MouseScurry(pattern) => pattern.next_step(rng, parameters),
+            Pattern::BirdFlutter(pattern) => pattern.next_step(rng, parameters),
+            Pattern::SnakeSlither(pattern) => pattern.next_step(rng, parameters),
+            Pattern::HideAndPeek(pattern) => pattern.next_step(rng, parameters),
+        }
+    }
+}
+
+/// Endless forward/reverse movements with random duty and duration
+#[derive(Default)]
+pub struct RandomWalk {
+    last_direction: Option<MotorDirection>,
+}
+
+impl MotionPattern for RandomWalk {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        let direction = next_direction(&mut self.last_direction);
+        let duty = random_duty(rng, parameters.min_duty.raw(), parameters.max_duty.raw());
+        let duration_ms = random_duration(
+            rng,
+            parameters.min_movement_duration,
+            parameters.max_movement_duration,
+        );
+        move_step(parameters, direction, duty, duration_ms)
+    }
+}
+
+/// A few short, fast bursts in random directions followed by a pause
+#[derive(Default)]
+pub struct MouseScurry {
+    bursts_left: u8,
+}
+
+impl MotionPattern for MouseScurry {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        if self.bursts_left == 0 {
+            self.bursts_left = rng.gen_range(2..=4);
+            let duration_ms = random_duration(
+                rng,
+                parameters.min_pause_duration,
+                parameters.max_pause_duration,
+            );
+            return MotionStep::Pause { duration_ms };
+        }
+        self.bursts_left -= 1;
+
+        let direction = if rng.gen_bool(0.5) {
+            MotorDirection::Forward
+        } else {
+            MotorDirection::Reverse
+        };
+        let (min_duty, max_duty) =
+            upper_part(parameters.min_duty.raw(), parameters.max_duty.raw(), 500);
+        let duty = random_duty(rng, min_duty, max_duty);
+        let (min_duration, max_duration) = lower_part(
+            parameters.min_movement_duration,
+            parameters.max_movement_duration,
+            250,
+        );
+        let duration_ms = random_duration(rng, min_duration, max_duration);
+        move_step(parameters, direction, duty, duration_ms)
+    }
+}
+
+/// Rapid, small back-and-forth oscillations without ramping
+#[derive(Default)]
+pub struct BirdFlutter {
+    last_direction: Option<MotorDirection>,
+}
+
+impl MotionPattern for BirdFlutter {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        let direction = next_direction(&mut self.last_direction);
+        let (min_duty, max_duty) =
+            lower_part(parameters.min_duty.raw(), parameters.max_duty.raw(), 330);
+        let duty = random_duty(rng, min_duty, max_duty);
+        let flutter_duration = parameters.min_movement_duration / 2;
+        let duration_ms = random_duration(rng, flutter_duration / 2, flutter_duration);
+        MotionStep::Move {
+            direction,
+            duty,
+            duration_ms,
+            ramp: Ramp::default(),
+        }
+    }
+}
+
+/// Slow, long movements that keep the same direction for several steps
+#[derive(Default)]
+pub struct SnakeSlither {
+    direction: Option<MotorDirection>,
+    steps_until_turn: u8,
+}
+
+impl MotionPattern for SnakeSlither {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        if self.steps_until_turn == 0 {
+            next_direction(&mut self.direction);
+            self.steps_until_turn = rng.gen_range(3..=5);
+        }
+        self.steps_until_turn -= 1;
+        let direction = self.direction.unwrap_or(MotorDirection::Forward);
+
+        let (min_duty, max_duty) =
+            lower_part(parameters.min_duty.raw(), parameters.max_duty.raw(), 250);
+        let duty = random_duty(rng, min_duty, max_duty);
+        let (min_duration, max_duration) = upper_part(
+            parameters.min_movement_duration,
+            parameters.max_movement_duration,
+            500,
+        );
+        let duration_ms = random_duration(rng, min_duration, max_duration);
+        MotionStep::Move {
+            direction,
+            duty,
+            duration_ms,
+            ramp: Ramp {
+                profile: RampProfile::SCurve,
+                duration_ms: duration_ms / 2,
+            },
+        }
+    }
+}
+
+/// Long stillness followed by a single dart at full speed
+#[derive(Default)]
+pub struct HideAndPeek {
+    hidden: bool,
+    last_direction: Option<MotorDirection>,
+}
+
+impl MotionPattern for HideAndPeek {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        self.hidden = !self.hidden;
+        if self.hidden {
+            let duration_ms = random_duration(
+                rng,
+                parameters.max_pause_duration,
+                parameters.max_pause_duration.saturating_mul(2),
+            );
+            return MotionStep::Pause { duration_ms };
+        }
+
+        let direction = next_direction(&mut self.last_direction);
+        let (min_duration, max_duration) = lower_part(
+            parameters.min_movement_duration,
+            parameters.max_movement_duration,
+            250,
+        );
+        let duration_ms = random_duration(rng, min_duration, max_duration);
+        MotionStep::Move {
+            direction,
+            duty: parameters.max_duty,
+            duration_ms,
+            ramp: Ramp::default(),
+        }
+    }
+}
+
+fn next_direction(last_direction: &mut Option<MotorDirection>) -> MotorDirection {
+    let direction = match last_direction {
+        Some(direction) => direction.reversed(),
+        None => MotorDirection::Forward,
+    };
+    *last_direction = Some(direction);
+    direction
+}
+
+fn move_step(
+    parameters: &MotionParameters,
+    direction: MotorDirection,
+    duty: Duty,
+    duration_ms: u16,
+) -> MotionStep {
+    // Leave at least half of the movement at the target duty
+    let ramp = Ramp {
+        profile: parameters.ramp_profile,
+        duration_ms: parameters.max_ramp_duration.min(duration_ms / 2),
+    };
+    MotionStep::Move {
+        direction,
+        duty,
+        duration_ms,
+        ramp,
+    }
+}
+
+fn random_duty<R: Rng>(rng: &mut R, min_raw: u16, max_raw: u16) -> Duty {
+    Duty::from_raw(random_in(rng, min_raw..=max_raw))
+}
+
+fn random_duration<R: Rng>(rng: &mut R, min: u16, max: u16) -> u16 {
+    random_in(rng, min..=max)
+}
+
+fn random_in<R: Rng>(rng: &mut R, range: RangeInclusive<u16>) -> u16 {
+    let (start, end) = range.into_inner();
+    // Guard against a snapshot where the current max has not caught up with the min yet
+    rng.gen_range(start..=end.max(start))
+}
+
+/// Lowest `permille` of the range between `min` and `max`
+fn lower_part(min: u16, max: u16, permille: u16) -> (u16, u16) {
+    let max = max.max(min);
+    let end = map_range_clamped(permille, 0, 1000, min, max, Rounding::Floor).unwrap_or(max);
+    (min, end)
+}
+
+/// Highest `permille` of the range between `min` and `max`
+fn upper_part(min: u16, max: u16, permille: u16) -> (u16, u16) {
+    let max = max.max(min);
+    let start = map_range_clamped(permille, 0, 1000, max, min, Rounding::Ceil).unwrap_or(min);
+    (start, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const PARAMETERS: MotionParameters = MotionParameters {
+        min_duty: Duty::from_percent(20),
+        max_duty: Duty::from_percent(80),
+        min_movement_duration: 200,
+        max_movement_duration: 2_000,
+        ramp_profile: RampProfile::Linear,
+        max_ramp_duration: 300,
+        pause_probability_percent: 30,
+        min_pause_duration: 500,
+        max_pause_duration: 3_000,
+    };
+
+    fn steps(kind: PatternKind, seed: u64, count: usize) -> Vec<MotionStep> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut pattern = Pattern::new(kind);
+        (0..count)
+            .map(|_| pattern.next_step(&mut rng, &PARAMETERS))
+            .collect()
+    }
+
+    fn direction(step: &MotionStep) -> MotorDirection {
+        match step {
+            MotionStep::Move { direction, .. } => *direction,
+            MotionStep::Pause { .. } => panic!("expected a movement, got {:?}", step),
+        }
+    }
+
+    #[test]
+    fn mouse_scurry_bursts_two_to_four_times_between_pauses() {
+        let steps = steps(PatternKind::MouseScurry, 3, 400);
+        assert!(matches!(steps[0], MotionStep::Pause { .. }));
+
+        let mut burst_counts = Vec::new();
+        let mut bursts = 0;
+        for step in &steps[1..] {
+            match step {
+                MotionStep::Pause { duration_ms } => {
+                    assert!((500..=3_000).contains(duration_ms));
+                    burst_counts.push(bursts);
+                    bursts = 0;
+                }
+                MotionStep::Move {
+                    duty, duration_ms, ..
+                } => {
+                    // Upper half of the duty range, lowest quarter of the durations
+                    assert!(*duty >= Duty::from_percent(50), "{}", duty);
+                    assert!((200..=650).contains(duration_ms), "{}", duration_ms);
+                    bursts += 1;
+                }
+            }
+        }
+        assert!(burst_counts.iter().all(|count| (2..=4).contains(count)));
+        for count in 2..=4 {
+            assert!(burst_counts.contains(&count), "never saw {} bursts", count);
+        }
+    }
+
+    #[test]
+    fn bird_flutter_oscillates_quickly_without_ramping() {
+        let steps = steps(PatternKind::BirdFlutter, 5, 200);
+        for (index, step) in steps.iter().enumerate() {
+            let MotionStep::Move {
+                direction,
+                duty,
+                duration_ms,
+                ramp,
+            } = step
+            else {
+                panic!("bird flutter paused at step {}", index);
+            };
+            // A quarter to half of the shortest movement
+            assert!((50..=100).contains(duration_ms), "{}", duration_ms);
+            assert!(*duty <= Duty::from_percent(40), "{}", duty);
+            assert_eq!(ramp.duration_ms, 0);
+            let expected = if index % 2 == 0 {
+                MotorDirection::Forward
+            } else {
+                MotorDirection::Reverse
+            };
+            assert_eq!(*direction, expected);
+        }
+    }
+
+    #[test]
+    fn snake_slither_turns_every_three_to_five_steps() {
+        let steps = steps(PatternKind::SnakeSlither, 11, 400);
+        let mut run_lengths = Vec::new();
+        let mut run_direction = direction(&steps[0]);
+        let mut run_length = 0;
+        for step in &steps {
+            if direction(step) == run_direction {
+                run_length += 1;
+            } else {
+                assert_eq!(direction(step), run_direction.reversed());
+                run_lengths.push(run_length);
+                run_direction = direction(step);
+                run_length = 1;
+            }
+            let MotionStep::Move {
+                duration_ms, ramp, ..
+            } = step
+            else {
+                unreachable!()
+            };
+            assert!(*duration_ms >= 1_100, "{}", duration_ms);
+            assert_eq!(ramp.profile, RampProfile::SCurve);
+            assert_eq!(ramp.duration_ms, duration_ms / 2);
+        }
+        assert_eq!(direction(&steps[0]), MotorDirection::Forward);
+        assert!(run_lengths.iter().all(|length| (3..=5).contains(length)));
+        for length in 3..=5 {
+            assert!(run_lengths.contains(&length), "never ran {} steps", length);
+        }
+    }
+
+    #[test]
+    fn hide_and_peek_alternates_hiding_and_darting() {
+        let steps = steps(PatternKind::HideAndPeek, 13, 100);
+        for (index, step) in steps.iter().enumerate() {
+            if index % 2 == 0 {
+                let MotionStep::Pause { duration_ms } = *step else {
+                    panic!("expected to hide at step {}", index);
+                };
+                assert!((3_000..=6_000).contains(&duration_ms), "{}", duration_ms);
+                continue;
+            }
+
+            let MotionStep::Move {
+                direction, duty, ..
+            } = *step
+            else {
+                panic!("expected to dart at step {}", index);
+            };
+            assert_eq!(duty, PARAMETERS.max_duty);
+            let expected = if index % 4 == 1 {
+                MotorDirection::Forward
+            } else {
+                MotorDirection::Reverse
+            };
+            assert_eq!(direction, expected);
+        }
+    }
+
+    #[test]
+    fn random_values_tolerate_a_max_below_the_min() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(random_duration(&mut rng, 300, 100), 300);
+        assert_eq!(random_duty(&mut rng, 400, 400), Duty::from_raw(400));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::calibration::VoltageRange;
+use crate::curve::{Curve, CURVE_SCALE};
+use crate::filter::{Filter, FilterConfig};
+use crate::map_range::{map_range_clamped, MapRangeError, Rounding};
+
+/// Source of single voltage samples, e.g. an ADC pin
+pub trait AdcSource {
+    /// Yields to other tasks instead of spinning while a conversion is in progress
+    async fn read_mv(&mut self) -> u16;
+}
+
+/// Everything that distinguishes one knob from another
+#[derive(Debug, Clone, Copy)]
+pub struct PotentiometerConfig {
+    pub name: &'static str,
+    pub sample_count: u16, // Number of samples to average
+    pub filter: FilterConfig,
+    pub min_voltage: u16, // mV
+    pub max_voltage: u16, // mV
+    pub min_output: u16,
+    pub max_output: u16,
+    pub curve: Curve,
+    pub takeover_threshold: u16, // Output travel that takes a parameter back from a remote value
+    /// Receives the output and whether the knob travelled past the takeover threshold since the
+    /// sink last accepted a value, returns whether it accepted this one
+    pub sink: fn(u16, bool) -> bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PotentiometerReading {
+    pub raw_voltage: u16, // mV, averaged
+    pub voltage: u16,     // mV, filtered
+    pub value: u16,
+}
+
+/// Turns raw samples of a knob into its output value
+pub struct PotentiometerMonitor {
+    config: PotentiometerConfig,
+    filter: Filter,
+    previous_value: Option<u16>,
+    accepted_value: Option<u16>,
+}
+
+impl PotentiometerMonitor {
+    pub fn new(config: PotentiometerConfig) -> Self {
+        Self {
+            config,
+            filter: Filter::new(config.filter, config.min_voltage, config.max_voltage),
+            previous_value: None,
+            accepted_value: None,
+        }
+    }
+
+    /// Maps the given voltage range onto the output range from now on
+    pub fn set_voltage_range(&mut self, range: VoltageRange) {
+        if (range.min, range.max) != (self.config.min_voltage, self.config.max_voltage) {
+            self.config.min_voltage = range.min;
+            self.config.max_voltage = range.max;
+            self.filter.set_voltage_range(range.min, range.max);
+        }
+    }
+
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.config.curve = curve;
+    }
+
+    /// Filters a batch of samples and maps it to the output range, handing the result to the sink
+    /// whenever it changed so values set elsewhere survive while the knob is left alone
+    pub async fn update<S: AdcSource>(&mut self, source: &mut S) -> PotentiometerReading {
+        let raw_voltage = self.filter.average(source, self.config.sample_count).await;
+        let voltage = self.filter.apply(raw_voltage);
+        let config = &self.config;
+        let value = map(voltage, config).unwrap_or(config.min_output);
+        if self.previous_value.replace(value) != Some(value) {
+            let moved_past_threshold = self.accepted_value.map_or(true, |accepted| {
+                accepted.abs_diff(value) > config.takeover_threshold
+            });
+            if (config.sink)(value, moved_past_threshold) {
+                self.accepted_value = Some(value);
+            }
+        }
+
+        PotentiometerReading {
+            raw_voltage,
+            voltage,
+            value,
+        }
+    }
+}
+
+fn map(voltage: u16, config: &PotentiometerConfig) -> Result<u16, MapRangeError> {
+    let position = map_range_clamped(
+        voltage as u32,
+        config.min_voltage.into(),
+        config.max_voltage.into(),
+        0,
+        CURVE_SCALE,
+        Rounding::Floor,
+    )?;
+    // Rounding to the nearest value lets the knob reach both ends of a descending output range too
+    map_range_clamped(
+        config.curve.apply(position) as u16,
+        0,
+        CURVE_SCALE as u16,
+        config.min_output,
+        config.max_output,
+        Rounding::Nearest,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::exponential;
+    use crate::fake_adc::FakeAdc;
+    use core::cell::{Cell, RefCell};
+    use embassy_futures::block_on;
+
+    std::thread_local! {
+        static SINK_CALLS: RefCell<Vec<(u16, bool)>> = const { RefCell::new(Vec::new()) };
+        static SINK_ACCEPTS: Cell<bool> = const { Cell::new(true) };
+    }
+
+    fn recording_sink(value: u16, moved_past_threshold: bool) -> bool {
+        SINK_CALLS.with(|calls| calls.borrow_mut().push((value, moved_past_threshold)));
+        SINK_ACCEPTS.with(Cell::get)
+    }
+
+    fn take_sink_calls() -> Vec<(u16, bool)> {
+        SINK_CALLS.with(RefCell::take)
+    }
+
+    const KNOB: PotentiometerConfig = PotentiometerConfig {
+        name: "test",
+        sample_count: 4,
+        filter: FilterConfig {
+            median_window: 1,
+            ema_alpha_permille: 1000,
+            deadband: 0,
+        },
+        min_voltage: 0,    // mV
+        max_voltage: 3000, // mV
+        min_output: 0,
+        max_output: 1000,
+        curve: Curve::Linear,
+        takeover_threshold: 100,
+        sink: recording_sink,
+    };
+
+    fn read(monitor: &mut PotentiometerMonitor, voltage: u16) -> u16 {
+        block_on(monitor.update(&mut FakeAdc::new(vec![voltage]))).value
+    }
+
+    #[test]
+    fn maps_the_voltage_range_onto_the_output_range() {
+        let mut monitor = PotentiometerMonitor::new(KNOB);
+        assert_eq!(read(&mut monitor, 0), 0);
+        assert_eq!(read(&mut monitor, 1500), 500);
+        assert_eq!(read(&mut monitor, 3000), 1000);
+        assert_eq!(read(&mut monitor, 3300), 1000);
+    }
+
+    #[test]
+    fn descending_output_range_reaches_both_ends() {
+        let mut monitor = PotentiometerMonitor::new(PotentiometerConfig {
+            min_output: 1000,
+            max_output: 0,
+            ..KNOB
+        });
+        assert_eq!(read(&mut monitor, 0), 1000);
+        assert_eq!(read(&mut monitor, 1500), 500);
+        assert_eq!(read(&mut monitor, 3000), 0);
+    }
+
+    #[test]
+    fn reading_averages_the_configured_number_of_samples() {
+        let mut monitor = PotentiometerMonitor::new(KNOB);
+        let mut adc = FakeAdc::new(vec![1000, 2000]);
+        let reading = block_on(monitor.update(&mut adc));
+        assert_eq!(adc.reads(), KNOB.sample_count as usize);
+        assert_eq!(reading.raw_voltage, 1500);
+        assert_eq!(reading.value, 500);
+    }
+
+    #[test]
+    fn curve_shapes_the_output() {
+        let mut monitor = PotentiometerMonitor::new(PotentiometerConfig {
+            curve: Curve::Exponential,
+            ..KNOB
+        });
+        assert_eq!(read(&mut monitor, 1500) as u32, exponential(500, 4));
+
+        monitor.set_curve(Curve::Linear);
+        assert_eq!(read(&mut monitor, 1500), 500);
+    }
+
+    #[test]
+    fn calibrated_range_spans_the_whole_output() {
+        let mut monitor = PotentiometerMonitor::new(KNOB);
+        monitor.set_voltage_range(VoltageRange {
+            min: 1000,
+            max: 2000,
+        });
+        assert_eq!(read(&mut monitor, 900), 0);
+        assert_eq!(read(&mut monitor, 1500), 500);
+        assert_eq!(read(&mut monitor, 2100), 1000);
+    }
+
+    #[test]
+    fn sink_only_hears_about_changes() {
+        let mut monitor = PotentiometerMonitor::new(KNOB);
+        read(&mut monitor, 1500);
+        read(&mut monitor, 1500);
+        read(&mut monitor, 1800);
+        assert_eq!(take_sink_calls(), vec![(500, true), (600, false)]);
+    }
+
+    #[test]
+    fn takeover_threshold_counts_from_the_last_accepted_value() {
+        let mut monitor = PotentiometerMonitor::new(KNOB);
+        SINK_ACCEPTS.with(|accepts| accepts.set(false));
+        read(&mut monitor, 1500);
+        SINK_ACCEPTS.with(|accepts| accepts.set(true));
+        read(&mut monitor, 1560);
+        read(&mut monitor, 1800);
+        read(&mut monitor, 2200);
+        assert_eq!(
+            take_sink_calls(),
+            vec![(500, true), (520, true), (600, false), (733, true)]
+        );
+    }
+}
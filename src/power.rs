@@ -0,0 +1,125 @@
+/// When napping in light sleep pays off during a pause
+#[derive(Debug, Clone, Copy)]
+pub struct LightSleepConfig {
+    pub min_nap: u32,        // ms, shorter naps cost more than they save
+    pub max_nap: u32,        // ms, bounds how late knob moves are noticed
+    pub wake_up_margin: u32, // ms, so the next movement starts on time
+}
+
+impl LightSleepConfig {
+    /// How long to nap at `now` for a pause ending at `deadline`, both in ms since boot
+    pub fn nap_duration(&self, now: u64, deadline: u64) -> Option<u32> {
+        let available = deadline
+            .saturating_sub(now)
+            .saturating_sub(self.wake_up_margin as u64);
+        let nap = available.min(self.max_nap as u64) as u32;
+        (nap >= self.min_nap).then_some(nap)
+    }
+}
+
+/// Supply current of the chip alone, the motor is off whenever it naps
+#[derive(Debug, Clone, Copy)]
+pub struct SupplyCurrent {
+    pub active: u32,      // µA
+    pub light_sleep: u32, // µA
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightSleepStats {
+    pub naps: u32,
+    pub asleep: u64,    // ms
+    pub timer_lag: u64, // ms the system timer missed while asleep
+}
+
+impl LightSleepStats {
+    /// `slept` as measured by the RTC, `timer_elapsed` as measured by the system timer
+    pub fn record_nap(&mut self, slept: u64, timer_elapsed: u64) {
+        self.naps += 1;
+        self.asleep += slept;
+        self.timer_lag += slept.saturating_sub(timer_elapsed);
+    }
+
+    /// Time since boot for a system timer reading, including what the timer missed while asleep
+    pub fn uptime(&self, timer_now: u64) -> u64 {
+        timer_now + self.timer_lag
+    }
+
+    pub fn asleep_permille(&self, timer_now: u64) -> u16 {
+        match self.uptime(timer_now) {
+            0 => 0,
+            uptime => (self.asleep.min(uptime) * 1000 / uptime) as u16,
+        }
+    }
+
+    /// Average current saved since boot compared to staying awake, in µA
+    pub fn estimated_saving(&self, timer_now: u64, current: &SupplyCurrent) -> u32 {
+        let saved_while_asleep = current.active.saturating_sub(current.light_sleep);
+        (saved_while_asleep as u64 * self.asleep_permille(timer_now) as u64 / 1000) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: LightSleepConfig = LightSleepConfig {
+        min_nap: 50,
+        max_nap: 1_000,
+        wake_up_margin: 10,
+    };
+
+    const CURRENT: SupplyCurrent = SupplyCurrent {
+        active: 20_000,
+        light_sleep: 200,
+    };
+
+    #[test]
+    fn naps_leave_the_wake_up_margin_and_respect_the_bounds() {
+        assert_eq!(CONFIG.nap_duration(1_000, 1_500), Some(490));
+        assert_eq!(CONFIG.nap_duration(1_000, 1_060), Some(50));
+        assert_eq!(CONFIG.nap_duration(1_000, 1_059), None);
+        assert_eq!(CONFIG.nap_duration(1_000, 5_000), Some(1_000));
+    }
+
+    #[test]
+    fn pauses_too_short_to_nap_in_are_skipped() {
+        assert_eq!(CONFIG.nap_duration(1_000, 1_010), None);
+        assert_eq!(CONFIG.nap_duration(1_000, 1_005), None);
+        // A deadline already passed does not wrap around into a long nap
+        assert_eq!(CONFIG.nap_duration(1_000, 900), None);
+    }
+
+    #[test]
+    fn record_nap_adds_what_the_system_timer_missed() {
+        let mut stats = LightSleepStats::default();
+        stats.record_nap(500, 20);
+        stats.record_nap(300, 300);
+        // A timer that ran ahead of the RTC never takes uptime away
+        stats.record_nap(100, 150);
+        assert_eq!(
+            stats,
+            LightSleepStats {
+                naps: 3,
+                asleep: 900,
+                timer_lag: 480,
+            }
+        );
+        assert_eq!(stats.uptime(1_000), 1_480);
+    }
+
+    #[test]
+    fn statistics_at_zero_uptime_do_not_divide_by_zero() {
+        let stats = LightSleepStats::default();
+        assert_eq!(stats.asleep_permille(0), 0);
+        assert_eq!(stats.estimated_saving(0, &CURRENT), 0);
+    }
+
+    #[test]
+    fn saving_scales_with_the_time_asleep() {
+        let mut stats = LightSleepStats::default();
+        stats.record_nap(1_000, 0);
+        // Asleep for 1 s out of 4 s since boot
+        assert_eq!(stats.asleep_permille(3_000), 250);
+        assert_eq!(stats.estimated_saving(3_000, &CURRENT), 4_950);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::curve;
+use crate::duty::Duty;
+use crate::map_range::map_range;
+use crate::motion::MotorDirection;
+use crate::motor_driver::MotorDriver;
+use embassy_time::Duration;
+
+const RAMP_SCALE: u32 = 1000; // Ramp progress is expressed in permille
+const RAMP_STEP_INTERVAL: u16 = 20; // ms
+const EXPONENTIAL_RAMP_DOUBLINGS: u32 = 6; // Steepness of the exponential ramp
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RampProfile {
+    #[default]
+    Linear,
+    SCurve,
+    Exponential,
+}
+
+impl RampProfile {
+    /// Permille of the duty change that should be applied after `elapsed` out of `total` steps
+    pub fn progress(self, elapsed: u32, total: u32) -> u32 {
+        if total == 0 || elapsed >= total {
+            return RAMP_SCALE;
+        }
+        let t = (elapsed * RAMP_SCALE / total) as u64;
+        let scale = RAMP_SCALE as u64;
+        let progress = match self {
+            RampProfile::Linear => t,
+            // Smoothstep: 3t² - 2t³
+            RampProfile::SCurve => t * t * (3 * scale - 2 * t) / (scale * scale),
+            RampProfile::Exponential => {
+                curve::exponential(t as u32, EXPONENTIAL_RAMP_DOUBLINGS) as u64
+            }
+        };
+        progress as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ramp {
+    pub profile: RampProfile,
+    pub duration_ms: u16,
+}
+
+/// Wraps a motor driver so duty changes are stepped over time instead of applied instantly
+pub struct RampedMotor<M: MotorDriver> {
+    motor: M,
+    signed_duty: i32, // Positive is forward, negative is reverse
+}
+
+impl<M: MotorDriver> RampedMotor<M> {
+    pub fn new(motor: M) -> Self {
+        Self {
+            motor,
+            signed_duty: 0,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.motor
+    }
+
+    /// Ramps from the current duty to the target one, passing through a standstill when the
+    /// direction changes
+    pub async fn ramp_to(&mut self, direction: MotorDirection, duty: Duty, ramp: Ramp) {
+        let steps = ramp.duration_ms / RAMP_STEP_INTERVAL;
+        if steps == 0 {
+            self.start_movement(direction, duty);
+            return;
+        }
+
+        let start = self.signed_duty;
+        let target = signed_duty(direction, duty);
+        for step in 1..=steps {
+            self.motor
+                .hold(Duration::from_millis(RAMP_STEP_INTERVAL.into()))
+                .await;
+            let progress = ramp.profile.progress(step.into(), steps.into()) as i32;
+            let step_duty =
+                map_range(progress, 0, RAMP_SCALE as i32, start, target).unwrap_or(target);
+            let step_direction = if step_duty >= 0 {
+                MotorDirection::Forward
+            } else {
+                MotorDirection::Reverse
+            };
+            self.set_duty(
+                step_direction,
+                Duty::from_raw(step_duty.unsigned_abs() as u16),
+            );
+        }
+    }
+}
+
+impl<M: MotorDriver> MotorDriver for RampedMotor<M> {
+    fn start_movement(&mut self, direction: MotorDirection, duty: Duty) {
+        self.motor.start_movement(direction, duty);
+        self.signed_duty = signed_duty(direction, duty);
+    }
+
+    fn set_duty(&mut self, direction: MotorDirection, duty: Duty) {
+        self.motor.set_duty(direction, duty);
+        self.signed_duty = signed_duty(direction, duty);
+    }
+
+    fn stop_coast(&mut self) {
+        self.motor.stop_coast();
+        self.signed_duty = 0;
+    }
+
+    fn brake(&mut self) {
+        self.motor.brake();
+        self.signed_duty = 0;
+    }
+
+    async fn hold(&mut self, duration: Duration) {
+        self.motor.hold(duration).await;
+    }
+}
+
+fn signed_duty(direction: MotorDirection, duty: Duty) -> i32 {
+    match direction {
+        MotorDirection::Forward => duty.raw().into(),
+        MotorDirection::Reverse => -i32::from(duty.raw()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_motor_driver::MockMotorDriver;
+    use crate::motor_driver::MotorCommand;
+    use embassy_futures::block_on;
+    use MotorCommand::{Brake, SetDuty, StartMovement, StopCoast};
+    use MotorDirection::{Forward, Reverse};
+
+    fn set_duty(direction: MotorDirection, raw: u16) -> MotorCommand {
+        SetDuty {
+            direction,
+            duty: Duty::from_raw(raw),
+        }
+    }
+
+    #[test]
+    fn zero_length_ramp_starts_at_once() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        block_on(motor.ramp_to(Reverse, Duty::from_percent(40), Ramp::default()));
+        let motor = motor.inner_mut();
+        assert_eq!(motor.now_ms(), 0);
+        assert_eq!(motor.log().len(), 1);
+        assert_eq!(
+            motor.last_command(),
+            Some(StartMovement {
+                direction: Reverse,
+                duty: Duty::from_percent(40),
+            })
+        );
+    }
+
+    #[test]
+    fn linear_ramp_steps_every_interval() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        let ramp = Ramp {
+            profile: RampProfile::Linear,
+            duration_ms: 100,
+        };
+        block_on(motor.ramp_to(Forward, Duty::from_raw(1000), ramp));
+        assert_eq!(
+            motor.inner_mut().log(),
+            [
+                (20, set_duty(Forward, 200)),
+                (40, set_duty(Forward, 400)),
+                (60, set_duty(Forward, 600)),
+                (80, set_duty(Forward, 800)),
+                (100, set_duty(Forward, 1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reversing_ramp_passes_through_standstill() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        motor.start_movement(Forward, Duty::from_raw(1000));
+        let ramp = Ramp {
+            profile: RampProfile::Linear,
+            duration_ms: 80,
+        };
+        block_on(motor.ramp_to(Reverse, Duty::from_raw(1000), ramp));
+        assert_eq!(
+            motor.inner_mut().log(),
+            [
+                (
+                    0,
+                    StartMovement {
+                        direction: Forward,
+                        duty: Duty::from_raw(1000)
+                    }
+                ),
+                (20, set_duty(Forward, 500)),
+                (40, set_duty(Forward, 0)),
+                (60, set_duty(Reverse, 500)),
+                (80, set_duty(Reverse, 1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ramp_continues_from_the_current_duty() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        let ramp = Ramp {
+            profile: RampProfile::Linear,
+            duration_ms: 40,
+        };
+        block_on(motor.ramp_to(Forward, Duty::from_raw(1000), ramp));
+        block_on(motor.ramp_to(Forward, Duty::from_raw(600), ramp));
+        assert_eq!(
+            motor.inner_mut().log()[2..],
+            [(60, set_duty(Forward, 800)), (80, set_duty(Forward, 600))]
+        );
+    }
+
+    #[test]
+    fn s_curve_ramp_rises_monotonically_to_the_target() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        let ramp = Ramp {
+            profile: RampProfile::SCurve,
+            duration_ms: 300,
+        };
+        block_on(motor.ramp_to(Forward, Duty::MAX, ramp));
+        let log = motor.inner_mut().log();
+        assert_eq!(log.len(), 15);
+        assert_eq!(log.last(), Some(&(300, set_duty(Forward, Duty::MAX.raw()))));
+        for pair in log.windows(2) {
+            let [(first_time, SetDuty { duty: first, .. }), (second_time, SetDuty { duty: second, .. })] =
+                pair
+            else {
+                panic!("unexpected commands {:?}", pair);
+            };
+            assert_eq!(second_time - first_time, RAMP_STEP_INTERVAL as u64);
+            assert!(first <= second);
+        }
+    }
+
+    #[test]
+    fn brake_then_coast_releases_after_the_brake_duration() {
+        let mut motor = RampedMotor::new(MockMotorDriver::new());
+        motor.start_movement(Forward, Duty::from_raw(1000));
+        block_on(motor.brake_then_coast(Duration::from_millis(100)));
+        // A ramp after braking starts from a standstill
+        let ramp = Ramp {
+            profile: RampProfile::Linear,
+            duration_ms: 40,
+        };
+        block_on(motor.ramp_to(Reverse, Duty::from_raw(1000), ramp));
+        assert_eq!(
+            motor.inner_mut().log()[1..],
+            [
+                (0, Brake),
+                (100, StopCoast),
+                (120, set_duty(Reverse, 500)),
+                (140, set_duty(Reverse, 1000)),
+            ]
+        );
+    }
+}
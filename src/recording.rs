@@ -0,0 +1,685 @@
+use crate::duty::Duty;
+use crate::map_range::{map_range_saturating, Rounding};
+use crate::motion::{MotionParameters, MotionStep, MotorDirection};
+use crate::motor_driver::MotorDriver;
+use crate::pattern::MotionPattern;
+use crate::ramp::{Ramp, RampProfile, RampedMotor};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use embassy_time::Duration;
+use rand::Rng;
+
+// Binary format: magic header, then per command a LEB128 delay since the previous command (ms) and
+// an opcode. Moves carry the raw target duty as little-endian u16, the ramp profile and the LEB128
+// ramp duration (ms). An end marker carries the delay between the last command and the end of the
+// recording.
+const MAGIC: [u8; 4] = *b"CTR2";
+const OPCODE_MOVE_FORWARD: u8 = 0;
+const OPCODE_MOVE_REVERSE: u8 = 1;
+const OPCODE_STOP: u8 = 2;
+const OPCODE_END: u8 = 0xFF;
+const MAX_COMMAND_LEN: usize = 12; // Delay varint (5 bytes) + opcode + duty + profile + ramp varint
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    MissingHeader,
+    Truncated,
+    UnknownOpcode(u8),
+    UnknownRampProfile(u8),
+    InvalidDelay,
+    InvalidHex,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingHeader => write!(f, "missing header"),
+            DecodeError::Truncated => write!(f, "truncated recording"),
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {}", opcode),
+            DecodeError::UnknownRampProfile(profile) => {
+                write!(f, "unknown ramp profile {}", profile)
+            }
+            DecodeError::InvalidDelay => write!(f, "invalid delay"),
+            DecodeError::InvalidHex => write!(f, "invalid hex encoding"),
+        }
+    }
+}
+
+/// A movement as it was commanded, before the ramp turned it into duty steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordedCommand {
+    Move {
+        direction: MotorDirection,
+        duty: Duty,
+        ramp: Ramp,
+    },
+    /// Braked, then left to coast
+    Stop,
+}
+
+/// Encoded sequence of timestamped movement commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    bytes: Vec<u8>,
+}
+
+impl Recording {
+    /// Validates an encoded recording, e.g. one received from a client
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let recording = Self {
+            bytes: bytes.to_vec(),
+        };
+        recording.decode()?;
+        Ok(recording)
+    }
+
+    /// Text-safe encoding for transferring recordings over HTTP
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(self.bytes.len() * 2);
+        for byte in &self.bytes {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        hex
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, DecodeError> {
+        let pairs = hex.trim().as_bytes().chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return Err(DecodeError::InvalidHex);
+        }
+        let bytes = pairs
+            .map(|pair| {
+                match (
+                    (pair[0] as char).to_digit(16),
+                    (pair[1] as char).to_digit(16),
+                ) {
+                    (Some(high), Some(low)) => Ok((high << 4 | low) as u8),
+                    _ => Err(DecodeError::InvalidHex),
+                }
+            })
+            .collect::<Result<Vec<u8>, DecodeError>>()?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Commands along with the delay since the previous one, followed by the trailing delay until
+    /// the end of the recording
+    pub fn decode(&self) -> Result<(Vec<(u32, RecordedCommand)>, u32), DecodeError> {
+        let body = self
+            .bytes
+            .strip_prefix(&MAGIC)
+            .ok_or(DecodeError::MissingHeader)?;
+        let mut reader = body.iter().copied();
+        let mut commands = Vec::new();
+        loop {
+            let delay_ms = read_varint(&mut reader)?;
+            let opcode = reader.next().ok_or(DecodeError::Truncated)?;
+            let command = match opcode {
+                OPCODE_MOVE_FORWARD | OPCODE_MOVE_REVERSE => {
+                    let low = reader.next().ok_or(DecodeError::Truncated)?;
+                    let high = reader.next().ok_or(DecodeError::Truncated)?;
+                    let profile = reader.next().ok_or(DecodeError::Truncated)?;
+                    let ramp_duration = read_varint(&mut reader)?;
+                    RecordedCommand::Move {
+                        direction: if opcode == OPCODE_MOVE_FORWARD {
+                            MotorDirection::Forward
+                        } else {
+                            MotorDirection::Reverse
+                        },
+                        duty: Duty::from_raw(u16::from_le_bytes([low, high])),
+                        ramp: Ramp {
+                            profile: decode_profile(profile)?,
+                            duration_ms: u16::try_from(ramp_duration)
+                                .map_err(|_| DecodeError::InvalidDelay)?,
+                        },
+                    }
+                }
+                OPCODE_STOP => RecordedCommand::Stop,
+                OPCODE_END => return Ok((commands, delay_ms)),
+                opcode => return Err(DecodeError::UnknownOpcode(opcode)),
+            };
+            commands.push((delay_ms, command));
+        }
+    }
+}
+
+/// Appends timestamped commands to a size-capped recording
+pub struct Recorder {
+    bytes: Vec<u8>,
+    max_len: usize,
+    last_timestamp_ms: u64,
+    full: bool,
+}
+
+impl Recorder {
+    pub fn new(start_timestamp_ms: u64, max_len: usize) -> Self {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        Self {
+            bytes,
+            max_len,
+            last_timestamp_ms: start_timestamp_ms,
+            full: false,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Commands are dropped once the recording is full
+    pub fn record(&mut self, timestamp_ms: u64, command: RecordedCommand) {
+        // Always leave room for the end marker
+        if self.full || self.bytes.len() + 2 * MAX_COMMAND_LEN > self.max_len {
+            self.full = true;
+            return;
+        }
+
+        self.write_delay(timestamp_ms);
+        match command {
+            RecordedCommand::Move {
+                direction,
+                duty,
+                ramp,
+            } => {
+                self.bytes.push(match direction {
+                    MotorDirection::Forward => OPCODE_MOVE_FORWARD,
+                    MotorDirection::Reverse => OPCODE_MOVE_REVERSE,
+                });
+                self.bytes.extend_from_slice(&duty.raw().to_le_bytes());
+                self.bytes.push(encode_profile(ramp.profile));
+                write_varint(&mut self.bytes, ramp.duration_ms.into());
+            }
+            RecordedCommand::Stop => self.bytes.push(OPCODE_STOP),
+        }
+    }
+
+    pub fn finish(mut self, end_timestamp_ms: u64) -> Recording {
+        self.write_delay(end_timestamp_ms);
+        self.bytes.push(OPCODE_END);
+        Recording { bytes: self.bytes }
+    }
+
+    fn write_delay(&mut self, timestamp_ms: u64) {
+        let delay_ms = timestamp_ms.saturating_sub(self.last_timestamp_ms);
+        self.last_timestamp_ms = timestamp_ms;
+        write_varint(&mut self.bytes, delay_ms.min(u32::MAX.into()) as u32);
+    }
+}
+
+/// Ramped motor that can record the movements it is commanded, rather than every duty step of
+/// their ramps, so a recording stays small enough to cover a whole play session
+pub struct RecordingMotor<M: MotorDriver> {
+    motor: RampedMotor<M>,
+    clock: fn() -> u64, // ms
+    recorder: Option<Recorder>,
+}
+
+impl<M: MotorDriver> RecordingMotor<M> {
+    pub fn new(motor: RampedMotor<M>, clock: fn() -> u64) -> Self {
+        Self {
+            motor,
+            clock,
+            recorder: None,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut RampedMotor<M> {
+        &mut self.motor
+    }
+
+    pub fn start_recording(&mut self, max_len: usize) {
+        self.recorder = Some(Recorder::new((self.clock)(), max_len));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let recorder = self.recorder.take()?;
+        Some(recorder.finish((self.clock)()))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder
+            .as_ref()
+            .is_some_and(|recorder| !recorder.is_full())
+    }
+
+    pub async fn ramp_to(&mut self, direction: MotorDirection, duty: Duty, ramp: Ramp) {
+        self.record(RecordedCommand::Move {
+            direction,
+            duty,
+            ramp,
+        });
+        self.motor.ramp_to(direction, duty, ramp).await;
+    }
+
+    pub async fn brake_then_coast(&mut self, brake_duration: Duration) {
+        self.record(RecordedCommand::Stop);
+        self.motor.brake_then_coast(brake_duration).await;
+    }
+
+    fn record(&mut self, command: RecordedCommand) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record((self.clock)(), command);
+        }
+    }
+}
+
+/// Plays a recording back as a motion pattern, looping once it reaches the end
+pub struct Replay {
+    commands: Vec<(u32, RecordedCommand)>,
+    end_delay_ms: u32,
+    position: usize,
+    remaining_ms: u32, // Of a command held for longer than a single step can last
+    time_stretch_permille: u16, // 1000 plays back at the recorded speed
+    mirrored: bool,    // Swaps forward and reverse
+}
+
+impl Replay {
+    pub fn new(
+        recording: &Recording,
+        time_stretch_permille: u16,
+        mirrored: bool,
+    ) -> Result<Self, DecodeError> {
+        let (commands, end_delay_ms) = recording.decode()?;
+        Ok(Self {
+            commands,
+            end_delay_ms,
+            position: 0,
+            remaining_ms: 0,
+            time_stretch_permille,
+            mirrored,
+        })
+    }
+
+    fn stretch(&self, duration_ms: u32) -> u32 {
+        map_range_saturating(
+            duration_ms,
+            0,
+            1000,
+            0,
+            self.time_stretch_permille.into(),
+            Rounding::Nearest,
+        )
+        .unwrap_or(duration_ms)
+    }
+}
+
+impl MotionPattern for Replay {
+    fn next_step<R: Rng>(&mut self, _rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        let Some(&(_, command)) = self.commands.get(self.position) else {
+            // Nothing was recorded, so hold still
+            return MotionStep::Pause {
+                duration_ms: parameters.min_pause_duration,
+            };
+        };
+
+        // A command lasts until the next one is issued, holds longer than a step are split up
+        let continued = self.remaining_ms > 0;
+        if !continued {
+            let hold_ms = match self.commands.get(self.position + 1) {
+                Some(&(delay_ms, _)) => delay_ms,
+                None => self.end_delay_ms,
+            };
+            // Never zero, so a burst of simultaneous commands still yields to other tasks
+            self.remaining_ms = self.stretch(hold_ms).max(1);
+        }
+        let duration_ms = self.remaining_ms.min(u16::MAX.into()) as u16;
+        self.remaining_ms -= u32::from(duration_ms);
+        if self.remaining_ms == 0 {
+            self.position = (self.position + 1) % self.commands.len();
+        }
+
+        match command {
+            RecordedCommand::Move {
+                direction,
+                duty,
+                ramp,
+            } => MotionStep::Move {
+                direction: if self.mirrored {
+                    direction.reversed()
+                } else {
+                    direction
+                },
+                duty,
+                duration_ms,
+                // The duty has been reached by the time a hold is continued
+                ramp: match continued {
+                    true => Ramp::default(),
+                    false => Ramp {
+                        profile: ramp.profile,
+                        duration_ms: self
+                            .stretch(ramp.duration_ms.into())
+                            .min(duration_ms.into()) as u16,
+                    },
+                },
+            },
+            RecordedCommand::Stop => MotionStep::Pause { duration_ms },
+        }
+    }
+}
+
+fn encode_profile(profile: RampProfile) -> u8 {
+    match profile {
+        RampProfile::Linear => 0,
+        RampProfile::SCurve => 1,
+        RampProfile::Exponential => 2,
+    }
+}
+
+fn decode_profile(profile: u8) -> Result<RampProfile, DecodeError> {
+    match profile {
+        0 => Ok(RampProfile::Linear),
+        1 => Ok(RampProfile::SCurve),
+        2 => Ok(RampProfile::Exponential),
+        profile => Err(DecodeError::UnknownRampProfile(profile)),
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Iterator<Item = u8>) -> Result<u32, DecodeError> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = reader.next().ok_or(DecodeError::Truncated)?;
+        let bits = (byte & 0x7F) as u32;
+        if shift == 28 && bits > 0x0F {
+            return Err(DecodeError::InvalidDelay);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidDelay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_motor_driver::MockMotorDriver;
+    use crate::motor_driver::MotorCommand;
+    use core::cell::Cell;
+    use embassy_futures::block_on;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const PARAMETERS: MotionParameters = MotionParameters {
+        min_duty: Duty::from_percent(20),
+        max_duty: Duty::from_percent(100),
+        min_movement_duration: 200,
+        max_movement_duration: 2_000,
+        ramp_profile: RampProfile::Linear,
+        max_ramp_duration: 300,
+        pause_probability_percent: 15,
+        min_pause_duration: 500,
+        max_pause_duration: 3_000,
+    };
+    const S_RAMP: Ramp = Ramp {
+        profile: RampProfile::SCurve,
+        duration_ms: 200,
+    };
+
+    std::thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(0) };
+    }
+
+    fn set_now(now_ms: u64) {
+        NOW.with(|now| now.set(now_ms));
+    }
+
+    fn recording_motor() -> RecordingMotor<MockMotorDriver> {
+        set_now(0);
+        RecordingMotor::new(RampedMotor::new(MockMotorDriver::new()), || {
+            NOW.with(Cell::get)
+        })
+    }
+
+    fn replay(
+        recording: &Recording,
+        time_stretch_permille: u16,
+        mirrored: bool,
+    ) -> Vec<MotionStep> {
+        let mut replay = Replay::new(recording, time_stretch_permille, mirrored).unwrap();
+        let mut rng = SmallRng::seed_from_u64(0);
+        (0..4)
+            .map(|_| replay.next_step(&mut rng, &PARAMETERS))
+            .collect()
+    }
+
+    /// Forward with an S-curve ramp for 1 s, a 500 ms stop, then reverse without a ramp for 400 ms
+    fn session() -> Recording {
+        let mut motor = recording_motor();
+        motor.start_recording(1536);
+        set_now(100);
+        block_on(motor.ramp_to(MotorDirection::Forward, Duty::from_percent(50), S_RAMP));
+        set_now(1_100);
+        block_on(motor.brake_then_coast(Duration::from_millis(100)));
+        set_now(1_600);
+        block_on(motor.ramp_to(
+            MotorDirection::Reverse,
+            Duty::from_percent(30),
+            Ramp::default(),
+        ));
+        set_now(2_000);
+        motor.stop_recording().unwrap()
+    }
+
+    #[test]
+    fn records_commanded_movements_not_ramp_steps() {
+        let mut motor = recording_motor();
+        motor.start_recording(1536);
+        block_on(motor.ramp_to(MotorDirection::Forward, Duty::from_percent(50), S_RAMP));
+        set_now(1_000);
+        let recording = motor.stop_recording().unwrap();
+
+        // The ramp still reached the motor step by step
+        assert_eq!(motor.inner_mut().inner_mut().log().len(), 10);
+        let (commands, end_delay_ms) = recording.decode().unwrap();
+        assert_eq!(
+            commands,
+            [(
+                0,
+                RecordedCommand::Move {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(50),
+                    ramp: S_RAMP,
+                }
+            )]
+        );
+        assert_eq!(end_delay_ms, 1_000);
+    }
+
+    #[test]
+    fn record_hex_parse_replay_round_trip() {
+        let recording = session();
+        let parsed = Recording::from_hex(&recording.to_hex()).unwrap();
+        assert_eq!(parsed, recording);
+
+        assert_eq!(
+            replay(&parsed, 1000, false),
+            [
+                MotionStep::Move {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(50),
+                    duration_ms: 1_000,
+                    ramp: S_RAMP,
+                },
+                MotionStep::Pause { duration_ms: 500 },
+                MotionStep::Move {
+                    direction: MotorDirection::Reverse,
+                    duty: Duty::from_percent(30),
+                    duration_ms: 400,
+                    ramp: Ramp::default(),
+                },
+                // Loops back to the start
+                MotionStep::Move {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(50),
+                    duration_ms: 1_000,
+                    ramp: S_RAMP,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_stretches_time_and_mirrors_directions() {
+        let steps = replay(&session(), 2000, true);
+        assert_eq!(
+            steps[..3],
+            [
+                MotionStep::Move {
+                    direction: MotorDirection::Reverse,
+                    duty: Duty::from_percent(50),
+                    duration_ms: 2_000,
+                    ramp: Ramp {
+                        profile: RampProfile::SCurve,
+                        duration_ms: 400,
+                    },
+                },
+                MotionStep::Pause { duration_ms: 1_000 },
+                MotionStep::Move {
+                    direction: MotorDirection::Forward,
+                    duty: Duty::from_percent(30),
+                    duration_ms: 800,
+                    ramp: Ramp::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replayed_steps_drive_the_motor_like_the_original() {
+        let mut replay = Replay::new(&session(), 1000, false).unwrap();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut motor = recording_motor();
+        for _ in 0..3 {
+            match replay.next_step(&mut rng, &PARAMETERS) {
+                MotionStep::Move {
+                    direction,
+                    duty,
+                    ramp,
+                    ..
+                } => block_on(motor.ramp_to(direction, duty, ramp)),
+                MotionStep::Pause { .. } => {
+                    block_on(motor.brake_then_coast(Duration::from_millis(100)))
+                }
+            }
+        }
+        let log = motor.inner_mut().inner_mut().log();
+        assert_eq!(log.len(), 10 + 2 + 1);
+        assert_eq!(
+            log[10..12]
+                .iter()
+                .map(|(_, command)| *command)
+                .collect::<Vec<_>>(),
+            [MotorCommand::Brake, MotorCommand::StopCoast]
+        );
+    }
+
+    #[test]
+    fn long_holds_are_split_into_several_steps() {
+        let mut recorder = Recorder::new(0, 1536);
+        recorder.record(
+            0,
+            RecordedCommand::Move {
+                direction: MotorDirection::Forward,
+                duty: Duty::from_percent(40),
+                ramp: S_RAMP,
+            },
+        );
+        recorder.record(100_000, RecordedCommand::Stop);
+        let recording = recorder.finish(170_000);
+
+        let mut replay = Replay::new(&recording, 1000, false).unwrap();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let steps: Vec<MotionStep> = (0..5)
+            .map(|_| replay.next_step(&mut rng, &PARAMETERS))
+            .collect();
+        let held = |duration_ms, ramp| MotionStep::Move {
+            direction: MotorDirection::Forward,
+            duty: Duty::from_percent(40),
+            duration_ms,
+            ramp,
+        };
+        assert_eq!(
+            steps,
+            [
+                held(u16::MAX, S_RAMP),
+                held(34_465, Ramp::default()),
+                MotionStep::Pause {
+                    duration_ms: u16::MAX
+                },
+                MotionStep::Pause { duration_ms: 4_465 },
+                held(u16::MAX, S_RAMP),
+            ]
+        );
+    }
+
+    #[test]
+    fn ramp_never_outlasts_an_interrupted_movement() {
+        let mut recorder = Recorder::new(0, 1536);
+        let command = RecordedCommand::Move {
+            direction: MotorDirection::Forward,
+            duty: Duty::from_percent(40),
+            ramp: S_RAMP,
+        };
+        recorder.record(0, command);
+        recorder.record(50, RecordedCommand::Stop);
+        let steps = replay(&recorder.finish(100), 1000, false);
+        assert!(matches!(
+            steps[0],
+            MotionStep::Move {
+                duration_ms: 50,
+                ramp: Ramp {
+                    duration_ms: 50,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn a_full_recording_keeps_minutes_of_play() {
+        let mut motor = recording_motor();
+        motor.start_recording(1536);
+        let mut now = 0;
+        let mut movements = 0;
+        while motor.is_recording() {
+            now += 1_000;
+            set_now(now);
+            block_on(motor.ramp_to(MotorDirection::Forward, Duty::from_percent(60), S_RAMP));
+            movements += 1;
+        }
+        assert!(movements > 150, "only {} movements fit", movements);
+        let recording = motor.stop_recording().unwrap();
+        assert!(recording.to_hex().len() <= 2 * 1536);
+    }
+
+    #[test]
+    fn invalid_recordings_are_rejected() {
+        assert_eq!(Recording::from_hex("0"), Err(DecodeError::InvalidHex));
+        assert_eq!(Recording::from_hex("zz"), Err(DecodeError::InvalidHex));
+        assert_eq!(Recording::from_hex("00ff"), Err(DecodeError::MissingHeader));
+        // Header, then a move cut off after its duty
+        assert_eq!(
+            Recording::from_bytes(b"CTR2\x00\x00\x00\x20"),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Recording::from_bytes(b"CTR2\x00\x07"),
+            Err(DecodeError::UnknownOpcode(7))
+        );
+        assert_eq!(
+            Recording::from_bytes(b"CTR2\x00\x00\x00\x20\x09\x00"),
+            Err(DecodeError::UnknownRampProfile(9))
+        );
+    }
+}
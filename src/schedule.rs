@@ -0,0 +1,365 @@
+use crate::flash_record::{self, MAGIC_LEN};
+use core::fmt;
+use serde::Serialize;
+
+const MAGIC: [u8; MAGIC_LEN] = *b"SCH1";
+pub const MAX_PLAY_SLOTS: usize = 8;
+pub const SCHEDULE_LEN: usize = flash_record::record_len(1 + MAX_PLAY_SLOTS * 4); // Count, slots
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// A play session starting at the same local time every day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaySlot {
+    pub start: u16,    // minutes after midnight
+    pub duration: u16, // minutes
+}
+
+impl PlaySlot {
+    /// Seconds left in the session at `second_of_day`, if it is running
+    fn remaining(self, second_of_day: u32) -> Option<u32> {
+        let elapsed = (second_of_day + SECONDS_PER_DAY - self.start as u32 * 60) % SECONDS_PER_DAY;
+        (self.duration as u32 * 60)
+            .checked_sub(elapsed)
+            .filter(|&remaining| remaining > 0)
+    }
+
+    /// Seconds from `second_of_day` until the session starts next, at most a day
+    fn until_start(self, second_of_day: u32) -> u32 {
+        (self.start as u32 * 60 + SECONDS_PER_DAY - second_of_day - 1) % SECONDS_PER_DAY + 1
+    }
+}
+
+/// Daily window in which the toy has to stay still, it may wrap past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct QuietHours {
+    pub start: u16, // minutes after midnight
+    pub end: u16,   // minutes after midnight
+}
+
+impl QuietHours {
+    /// A window such as `22:00-07:00`
+    pub fn parse(window: &str) -> Option<Self> {
+        let (start, end) = window.split_once('-')?;
+        let quiet_hours = Self {
+            start: parse_time(start.trim())?,
+            end: parse_time(end.trim())?,
+        };
+        quiet_hours.is_valid().then_some(quiet_hours)
+    }
+
+    pub fn is_valid(self) -> bool {
+        self.start < MINUTES_PER_DAY && self.end < MINUTES_PER_DAY && self.start != self.end
+    }
+
+    /// Seconds until the window ends, `None` outside of it
+    pub fn remaining(self, second_of_day: u32) -> Option<u32> {
+        self.as_slot().remaining(second_of_day)
+    }
+
+    pub fn contains(self, second_of_day: u32) -> bool {
+        self.remaining(second_of_day).is_some()
+    }
+
+    /// Seconds from `second_of_day` until the window starts next, at most a day
+    pub fn until_start(self, second_of_day: u32) -> u32 {
+        self.as_slot().until_start(second_of_day)
+    }
+
+    fn as_slot(self) -> PlaySlot {
+        PlaySlot {
+            start: self.start,
+            duration: (self.end + MINUTES_PER_DAY - self.start) % MINUTES_PER_DAY,
+        }
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleError {
+    InvalidSlot(usize), // Index of the slot that is not `HH:MM <minutes>`
+    TooManySlots,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::InvalidSlot(index) => write!(
+                f,
+                "slot {} must be `HH:MM <minutes>` with a duration under a day",
+                index + 1
+            ),
+            ScheduleError::TooManySlots => write!(f, "at most {} slots fit", MAX_PLAY_SLOTS),
+        }
+    }
+}
+
+/// Daily play sessions, in local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    slots: [PlaySlot; MAX_PLAY_SLOTS],
+    len: usize,
+}
+
+impl Schedule {
+    pub const EMPTY: Schedule = Schedule {
+        slots: [PlaySlot {
+            start: 0,
+            duration: 0,
+        }; MAX_PLAY_SLOTS],
+        len: 0,
+    };
+
+    /// Slots separated by commas or new lines, e.g. `07:00 5, 12:30 5, 18:00 5`
+    pub fn parse(source: &str) -> Result<Self, ScheduleError> {
+        let mut schedule = Self::EMPTY;
+        let entries = source
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty());
+        for (index, entry) in entries.enumerate() {
+            let slot = parse_slot(entry).ok_or(ScheduleError::InvalidSlot(index))?;
+            *schedule
+                .slots
+                .get_mut(index)
+                .ok_or(ScheduleError::TooManySlots)? = slot;
+            schedule.len = index + 1;
+        }
+        Ok(schedule)
+    }
+
+    pub fn slots(&self) -> &[PlaySlot] {
+        &self.slots[..self.len]
+    }
+
+    /// Seconds until the running session ends, `None` outside of sessions and during quiet hours
+    pub fn session_remaining(
+        &self,
+        second_of_day: u32,
+        quiet_hours: Option<QuietHours>,
+    ) -> Option<u32> {
+        if quiet_hours.is_some_and(|quiet_hours| quiet_hours.contains(second_of_day)) {
+            return None;
+        }
+        self.slots()
+            .iter()
+            .filter_map(|slot| slot.remaining(second_of_day))
+            .max()
+    }
+
+    /// Seconds until the next session starts, skipping sessions that start during quiet hours.
+    /// `None` when no session is left
+    pub fn until_next_session(
+        &self,
+        second_of_day: u32,
+        quiet_hours: Option<QuietHours>,
+    ) -> Option<u32> {
+        self.slots()
+            .iter()
+            .filter(|slot| {
+                !quiet_hours.is_some_and(|quiet_hours| quiet_hours.contains(slot.start as u32 * 60))
+            })
+            .map(|slot| slot.until_start(second_of_day))
+            .min()
+    }
+
+    pub fn to_bytes(self) -> [u8; SCHEDULE_LEN] {
+        flash_record::encode(MAGIC, |payload| {
+            payload[0] = self.len as u8;
+            let chunks = payload[1..].chunks_exact_mut(4);
+            for (slot, chunk) in self.slots().iter().zip(chunks) {
+                chunk[..2].copy_from_slice(&slot.start.to_le_bytes());
+                chunk[2..].copy_from_slice(&slot.duration.to_le_bytes());
+            }
+        })
+    }
+
+    /// `None` for erased flash, data written by another firmware or a corrupted schedule
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let payload = flash_record::decode(MAGIC, bytes, SCHEDULE_LEN)?;
+        let mut schedule = Self::EMPTY;
+        schedule.len = payload[0] as usize;
+        let chunks = payload[1..].chunks_exact(4);
+        for (slot, chunk) in schedule.slots.iter_mut().zip(chunks).take(schedule.len) {
+            slot.start = u16::from_le_bytes([chunk[0], chunk[1]]);
+            slot.duration = u16::from_le_bytes([chunk[2], chunk[3]]);
+            if !is_valid(*slot) {
+                return None;
+            }
+        }
+        (schedule.len <= MAX_PLAY_SLOTS).then_some(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for slot in self.slots() {
+            writeln!(
+                f,
+                "{:02}:{:02} {}",
+                slot.start / 60,
+                slot.start % 60,
+                slot.duration
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds since local midnight for a Unix time
+pub fn second_of_day(unix_seconds: u64, utc_offset_minutes: i16) -> u32 {
+    let local = unix_seconds as i64 + utc_offset_minutes as i64 * 60;
+    local.rem_euclid(SECONDS_PER_DAY as i64) as u32
+}
+
+fn parse_slot(entry: &str) -> Option<PlaySlot> {
+    let (start, duration) = entry.split_once(' ')?;
+    let slot = PlaySlot {
+        start: parse_time(start)?,
+        duration: duration.trim().parse().ok()?,
+    };
+    is_valid(slot).then_some(slot)
+}
+
+/// Minutes after midnight for `HH:MM`
+fn parse_time(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+fn is_valid(slot: PlaySlot) -> bool {
+    slot.start < MINUTES_PER_DAY && (1..MINUTES_PER_DAY).contains(&slot.duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u32 = 60 * 60; // s
+
+    fn at(hours: u32, minutes: u32) -> u32 {
+        hours * HOUR + minutes * 60
+    }
+
+    #[test]
+    fn parses_slots_and_rejects_invalid_ones() {
+        let schedule = Schedule::parse("07:00 5, 12:30 10\n18:00 15").unwrap();
+        assert_eq!(
+            schedule.slots(),
+            [
+                PlaySlot {
+                    start: 7 * 60,
+                    duration: 5
+                },
+                PlaySlot {
+                    start: 12 * 60 + 30,
+                    duration: 10
+                },
+                PlaySlot {
+                    start: 18 * 60,
+                    duration: 15
+                },
+            ]
+        );
+        assert_eq!(Schedule::parse(""), Ok(Schedule::EMPTY));
+        assert_eq!(
+            Schedule::parse("07:00 5, 24:00 5"),
+            Err(ScheduleError::InvalidSlot(1))
+        );
+        assert_eq!(
+            Schedule::parse("07:00 0"),
+            Err(ScheduleError::InvalidSlot(0))
+        );
+        assert_eq!(
+            Schedule::parse(&"07:00 5,".repeat(MAX_PLAY_SLOTS + 1)),
+            Err(ScheduleError::TooManySlots)
+        );
+    }
+
+    #[test]
+    fn next_session_is_the_closest_start_and_wraps_past_midnight() {
+        let schedule = Schedule::parse("07:00 5, 18:00 5").unwrap();
+        assert_eq!(schedule.until_next_session(at(6, 0), None), Some(HOUR));
+        assert_eq!(schedule.until_next_session(at(7, 0), None), Some(11 * HOUR));
+        assert_eq!(
+            schedule.until_next_session(at(7, 1), None),
+            Some(11 * HOUR - 60)
+        );
+        assert_eq!(schedule.until_next_session(at(23, 0), None), Some(8 * HOUR));
+        assert_eq!(Schedule::EMPTY.until_next_session(at(12, 0), None), None);
+    }
+
+    #[test]
+    fn sessions_starting_in_quiet_hours_are_skipped() {
+        let schedule = Schedule::parse("06:00 5, 18:00 5").unwrap();
+        let quiet_hours = QuietHours::parse("22:00-07:00");
+        assert_eq!(
+            schedule.until_next_session(at(23, 0), quiet_hours),
+            Some(19 * HOUR)
+        );
+        let always_quiet = Schedule::parse("23:00 5").unwrap();
+        assert_eq!(
+            always_quiet.until_next_session(at(12, 0), quiet_hours),
+            None
+        );
+    }
+
+    #[test]
+    fn running_session_reports_the_time_left() {
+        let schedule = Schedule::parse("23:55 10, 12:00 30").unwrap();
+        assert_eq!(schedule.session_remaining(at(23, 55), None), Some(600));
+        assert_eq!(schedule.session_remaining(at(0, 2), None), Some(180));
+        assert_eq!(schedule.session_remaining(at(0, 5), None), None);
+        assert_eq!(schedule.session_remaining(at(12, 20), None), Some(600));
+        assert_eq!(
+            schedule.session_remaining(at(0, 2), QuietHours::parse("00:00-06:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn quiet_hours_may_wrap_past_midnight() {
+        let quiet_hours = QuietHours::parse("22:00-07:00").unwrap();
+        assert!(quiet_hours.contains(at(23, 0)));
+        assert!(quiet_hours.contains(at(3, 0)));
+        assert!(!quiet_hours.contains(at(7, 0)));
+        assert!(!quiet_hours.contains(at(12, 0)));
+        assert_eq!(quiet_hours.remaining(at(23, 0)), Some(8 * HOUR));
+        assert_eq!(quiet_hours.until_start(at(21, 0)), HOUR);
+        assert_eq!(quiet_hours.to_string(), "22:00-07:00");
+        assert_eq!(QuietHours::parse("07:00-07:00"), None);
+        assert_eq!(QuietHours::parse("22:00"), None);
+    }
+
+    #[test]
+    fn second_of_day_applies_the_utc_offset() {
+        let noon_utc = 1_699_963_200; // 2023-11-14 12:00 UTC
+        assert_eq!(second_of_day(noon_utc, 0), at(12, 0));
+        assert_eq!(second_of_day(noon_utc, 90), at(13, 30));
+        assert_eq!(second_of_day(noon_utc, -13 * 60), at(23, 0));
+        assert_eq!(second_of_day(0, -60), at(23, 0));
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let schedule = Schedule::parse("07:00 5, 12:30 10").unwrap();
+        assert_eq!(Schedule::from_bytes(&schedule.to_bytes()), Some(schedule));
+        assert_eq!(Schedule::from_bytes(&[0xFF; SCHEDULE_LEN]), None);
+        let mut corrupted = schedule.to_bytes();
+        corrupted[MAGIC_LEN + 2] ^= 1;
+        assert_eq!(Schedule::from_bytes(&corrupted), None);
+    }
+}
@@ -0,0 +1,761 @@
+//! Line-based choreography scripts, e.g.
+//!
+//! ```text
+//! # Stalk, then pounce
+//! repeat 3 {
+//!     fwd 25% 1-2s ramp=s
+//!     pause 500-1500ms
+//! }
+//! random { rev 90% 300ms ramp=none | fwd 60-80% 400ms }
+//! ```
+
+use crate::duty::Duty;
+use crate::motion::{MotionParameters, MotionStep, MotorDirection};
+use crate::pattern::MotionPattern;
+use crate::ramp::{Ramp, RampProfile};
+use alloc::vec::Vec;
+use core::fmt;
+use rand::Rng;
+
+const MAX_INSTRUCTIONS_PER_STEP: usize = 1_000; // Bails out of scripts that never reach a step
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: u16,
+    pub column: u16,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    UnexpectedToken,
+    UnknownCommand,
+    ExpectedNumber,
+    NumberTooLarge,
+    ExpectedPercent,
+    ExpectedDurationUnit,
+    ExpectedOpeningBrace,
+    UnknownRampProfile,
+    InvalidRange,
+    ZeroRepeatCount,
+    DutyOutOfRange,
+    DurationOutOfRange,
+    EmptyScript,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match self.kind {
+            ParseErrorKind::UnexpectedCharacter(character) => {
+                write!(f, "unexpected character '{}'", character)
+            }
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of script"),
+            ParseErrorKind::UnexpectedToken => write!(f, "unexpected token"),
+            ParseErrorKind::UnknownCommand => {
+                write!(
+                    f,
+                    "unknown command, expected fwd, rev, pause, repeat or random"
+                )
+            }
+            ParseErrorKind::ExpectedNumber => write!(f, "expected a number"),
+            ParseErrorKind::NumberTooLarge => write!(f, "number is too large"),
+            ParseErrorKind::ExpectedPercent => write!(f, "expected '%' after the duty"),
+            ParseErrorKind::ExpectedDurationUnit => write!(f, "expected a duration unit (ms or s)"),
+            ParseErrorKind::ExpectedOpeningBrace => write!(f, "expected '{{'"),
+            ParseErrorKind::UnknownRampProfile => {
+                write!(f, "unknown ramp, expected linear, s, exp or none")
+            }
+            ParseErrorKind::InvalidRange => write!(f, "range minimum is larger than its maximum"),
+            ParseErrorKind::ZeroRepeatCount => write!(f, "repeat count must be at least 1"),
+            ParseErrorKind::DutyOutOfRange => write!(f, "duty must be between 0% and 100%"),
+            ParseErrorKind::DurationOutOfRange => write!(f, "duration must be at most 65535 ms"),
+            ParseErrorKind::EmptyScript => {
+                write!(f, "script does not contain any movement or pause")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RampSetting {
+    Default, // Use the profile from the motion parameters
+    Instant,
+    Profile(RampProfile),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instruction {
+    Move {
+        direction: MotorDirection,
+        min_duty: Duty,
+        max_duty: Duty,
+        min_duration: u16,
+        max_duration: u16,
+        ramp: RampSetting,
+    },
+    Pause {
+        min_duration: u16,
+        max_duration: u16,
+    },
+    RepeatStart {
+        count: u16,
+    },
+    RepeatEnd {
+        body_start: usize,
+    },
+    Choose {
+        branches: Vec<usize>,
+    },
+    Jump {
+        target: usize,
+    },
+}
+
+/// Parsed script, compiled into a flat list of instructions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    instructions: Vec<Instruction>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: end_position(source),
+            instructions: Vec::new(),
+        };
+        parser.parse_block()?;
+        if let Some(token) = parser.peek() {
+            return Err(token.error(ParseErrorKind::UnexpectedToken));
+        }
+
+        let has_step = parser.instructions.iter().any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::Move { .. } | Instruction::Pause { .. }
+            )
+        });
+        if !has_step {
+            return Err(ParseError {
+                line: 1,
+                column: 1,
+                kind: ParseErrorKind::EmptyScript,
+            });
+        }
+
+        Ok(Self {
+            instructions: parser.instructions,
+        })
+    }
+}
+
+/// Plays a script as a motion pattern, starting over once it reaches the end
+pub struct ScriptRunner {
+    script: Script,
+    program_counter: usize,
+    repeat_counters: Vec<u16>,
+}
+
+impl ScriptRunner {
+    pub fn new(script: Script) -> Self {
+        Self {
+            script,
+            program_counter: 0,
+            repeat_counters: Vec::new(),
+        }
+    }
+}
+
+impl MotionPattern for ScriptRunner {
+    fn next_step<R: Rng>(&mut self, rng: &mut R, parameters: &MotionParameters) -> MotionStep {
+        for _ in 0..MAX_INSTRUCTIONS_PER_STEP {
+            if self.program_counter >= self.script.instructions.len() {
+                self.program_counter = 0;
+                self.repeat_counters.clear();
+            }
+
+            let instruction = &self.script.instructions[self.program_counter];
+            self.program_counter += 1;
+            match instruction {
+                Instruction::Move {
+                    direction,
+                    min_duty,
+                    max_duty,
+                    min_duration,
+                    max_duration,
+                    ramp,
+                } => {
+                    let duty = Duty::from_raw(rng.gen_range(min_duty.raw()..=max_duty.raw()));
+                    let duration_ms = rng.gen_range(*min_duration..=*max_duration);
+                    let ramp_duration = parameters.max_ramp_duration.min(duration_ms / 2);
+                    let ramp = match ramp {
+                        RampSetting::Default => Ramp {
+                            profile: parameters.ramp_profile,
+                            duration_ms: ramp_duration,
+                        },
+                        RampSetting::Instant => Ramp::default(),
+                        RampSetting::Profile(profile) => Ramp {
+                            profile: *profile,
+                            duration_ms: ramp_duration,
+                        },
+                    };
+                    return MotionStep::Move {
+                        direction: *direction,
+                        duty,
+                        duration_ms,
+                        ramp,
+                    };
+                }
+                Instruction::Pause {
+                    min_duration,
+                    max_duration,
+                } => {
+                    return MotionStep::Pause {
+                        duration_ms: rng.gen_range(*min_duration..=*max_duration),
+                    };
+                }
+                Instruction::RepeatStart { count } => {
+                    self.repeat_counters.push(*count);
+                }
+                Instruction::RepeatEnd { body_start } => {
+                    if let Some(remaining) = self.repeat_counters.last_mut() {
+                        *remaining -= 1;
+                        if *remaining > 0 {
+                            self.program_counter = *body_start;
+                        } else {
+                            self.repeat_counters.pop();
+                        }
+                    }
+                }
+                Instruction::Choose { branches } => {
+                    self.program_counter = branches[rng.gen_range(0..branches.len())];
+                }
+                Instruction::Jump { target } => {
+                    self.program_counter = *target;
+                }
+            }
+        }
+
+        // Only reachable when randomly chosen branches keep skipping every step
+        MotionStep::Pause {
+            duration_ms: parameters.min_pause_duration,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind<'a> {
+    Word(&'a str),
+    Number(u32),
+    Percent,
+    Dash,
+    Equals,
+    OpenBrace,
+    CloseBrace,
+    Pipe,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: TokenKind<'a>,
+    line: u16,
+    column: u16,
+}
+
+impl Token<'_> {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = (line_index + 1) as u16;
+        let mut characters = line.char_indices().peekable();
+        while let Some((start, character)) = characters.next() {
+            let column = (start + 1) as u16;
+            let kind = match character {
+                '#' => break, // Comment until the end of the line
+                character if character.is_whitespace() => continue,
+                '%' => TokenKind::Percent,
+                '-' => TokenKind::Dash,
+                '=' => TokenKind::Equals,
+                '{' => TokenKind::OpenBrace,
+                '}' => TokenKind::CloseBrace,
+                '|' => TokenKind::Pipe,
+                '0'..='9' => {
+                    let mut end = start + 1;
+                    while let Some((index, '0'..='9')) = characters.peek().copied() {
+                        end = index + 1;
+                        characters.next();
+                    }
+                    let number = line[start..end].parse().map_err(|_| ParseError {
+                        line: line_number,
+                        column,
+                        kind: ParseErrorKind::NumberTooLarge,
+                    })?;
+                    TokenKind::Number(number)
+                }
+                character if character.is_ascii_alphabetic() => {
+                    let mut end = start + 1;
+                    while let Some((index, next)) = characters.peek().copied() {
+                        if !(next.is_ascii_alphanumeric() || next == '_') {
+                            break;
+                        }
+                        end = index + 1;
+                        characters.next();
+                    }
+                    TokenKind::Word(&line[start..end])
+                }
+                character => {
+                    return Err(ParseError {
+                        line: line_number,
+                        column,
+                        kind: ParseErrorKind::UnexpectedCharacter(character),
+                    })
+                }
+            };
+            tokens.push(Token {
+                kind,
+                line: line_number,
+                column,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Position just past the last character, used to report a premature end of the script
+fn end_position(source: &str) -> (u16, u16) {
+    let line_count = source.lines().count().max(1);
+    let last_line_length = source.lines().last().map_or(0, |line| line.len());
+    (line_count as u16, (last_line_length + 1) as u16)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    end: (u16, u16),
+    instructions: Vec<Instruction>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, ParseError> {
+        let token = self.peek().ok_or(ParseError {
+            line: self.end.0,
+            column: self.end.1,
+            kind: ParseErrorKind::UnexpectedEnd,
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_if(&mut self, kind: TokenKind) -> bool {
+        let matches = self.peek().is_some_and(|token| token.kind == kind);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Statements until the end of the script or of the enclosing braces/branch
+    fn parse_block(&mut self) -> Result<(), ParseError> {
+        while let Some(token) = self.peek() {
+            if matches!(token.kind, TokenKind::CloseBrace | TokenKind::Pipe) {
+                break;
+            }
+            self.parse_statement()?;
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self) -> Result<(), ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Word("fwd") => self.parse_move(MotorDirection::Forward),
+            TokenKind::Word("rev") => self.parse_move(MotorDirection::Reverse),
+            TokenKind::Word("pause") => {
+                let (min_duration, max_duration) = self.parse_duration()?;
+                self.instructions.push(Instruction::Pause {
+                    min_duration,
+                    max_duration,
+                });
+                Ok(())
+            }
+            TokenKind::Word("repeat") => self.parse_repeat(),
+            TokenKind::Word("random") => self.parse_random(),
+            TokenKind::Word(_) => Err(token.error(ParseErrorKind::UnknownCommand)),
+            _ => Err(token.error(ParseErrorKind::UnexpectedToken)),
+        }
+    }
+
+    fn parse_move(&mut self, direction: MotorDirection) -> Result<(), ParseError> {
+        let (min_duty, max_duty) = self.parse_duty()?;
+        let (min_duration, max_duration) = self.parse_duration()?;
+
+        let mut ramp = RampSetting::Default;
+        if self.peek().map(|token| token.kind) == Some(TokenKind::Word("ramp")) {
+            self.position += 1;
+            let token = self.next()?;
+            if token.kind != TokenKind::Equals {
+                return Err(token.error(ParseErrorKind::UnexpectedToken));
+            }
+            let token = self.next()?;
+            ramp = match token.kind {
+                TokenKind::Word("linear" | "lin") => RampSetting::Profile(RampProfile::Linear),
+                TokenKind::Word("s" | "scurve") => RampSetting::Profile(RampProfile::SCurve),
+                TokenKind::Word("exp" | "exponential") => {
+                    RampSetting::Profile(RampProfile::Exponential)
+                }
+                TokenKind::Word("none") => RampSetting::Instant,
+                _ => return Err(token.error(ParseErrorKind::UnknownRampProfile)),
+            };
+        }
+
+        self.instructions.push(Instruction::Move {
+            direction,
+            min_duty,
+            max_duty,
+            min_duration,
+            max_duration,
+            ramp,
+        });
+        Ok(())
+    }
+
+    fn parse_repeat(&mut self) -> Result<(), ParseError> {
+        let token = self.next()?;
+        let TokenKind::Number(count) = token.kind else {
+            return Err(token.error(ParseErrorKind::ExpectedNumber));
+        };
+        let count =
+            u16::try_from(count).map_err(|_| token.error(ParseErrorKind::NumberTooLarge))?;
+        // A body that never runs is almost certainly a mistake, and would leave nothing to play
+        // when it is all there is
+        if count == 0 {
+            return Err(token.error(ParseErrorKind::ZeroRepeatCount));
+        }
+        self.expect_open_brace()?;
+
+        let body_start = self.instructions.len() + 1;
+        self.instructions.push(Instruction::RepeatStart { count });
+        self.parse_block()?;
+        self.expect_close_brace()?;
+        self.instructions
+            .push(Instruction::RepeatEnd { body_start });
+        Ok(())
+    }
+
+    fn parse_random(&mut self) -> Result<(), ParseError> {
+        self.expect_open_brace()?;
+
+        let choose = self.instructions.len();
+        self.instructions.push(Instruction::Choose {
+            branches: Vec::new(),
+        });
+        let mut branches = Vec::new();
+        let mut jumps = Vec::new();
+        loop {
+            branches.push(self.instructions.len());
+            self.parse_block()?;
+            jumps.push(self.instructions.len());
+            self.instructions.push(Instruction::Jump { target: 0 });
+            if !self.next_if(TokenKind::Pipe) {
+                break;
+            }
+        }
+        self.expect_close_brace()?;
+
+        let end = self.instructions.len();
+        for jump in jumps {
+            self.instructions[jump] = Instruction::Jump { target: end };
+        }
+        self.instructions[choose] = Instruction::Choose { branches };
+        Ok(())
+    }
+
+    fn expect_open_brace(&mut self) -> Result<(), ParseError> {
+        let token = self.next()?;
+        if token.kind != TokenKind::OpenBrace {
+            return Err(token.error(ParseErrorKind::ExpectedOpeningBrace));
+        }
+        Ok(())
+    }
+
+    fn expect_close_brace(&mut self) -> Result<(), ParseError> {
+        let token = self.next()?;
+        if token.kind != TokenKind::CloseBrace {
+            return Err(token.error(ParseErrorKind::UnexpectedToken));
+        }
+        Ok(())
+    }
+
+    /// `<n>` or `<min>-<max>`, returning the token of the first number for error reporting
+    fn parse_range(&mut self) -> Result<(Token<'a>, u32, u32), ParseError> {
+        let token = self.next()?;
+        let TokenKind::Number(min) = token.kind else {
+            return Err(token.error(ParseErrorKind::ExpectedNumber));
+        };
+        let mut max = min;
+        if self.next_if(TokenKind::Dash) {
+            let max_token = self.next()?;
+            let TokenKind::Number(number) = max_token.kind else {
+                return Err(max_token.error(ParseErrorKind::ExpectedNumber));
+            };
+            max = number;
+        }
+        if min > max {
+            return Err(token.error(ParseErrorKind::InvalidRange));
+        }
+        Ok((token, min, max))
+    }
+
+    fn parse_duty(&mut self) -> Result<(Duty, Duty), ParseError> {
+        let (token, min, max) = self.parse_range()?;
+        let percent_token = self.next()?;
+        if percent_token.kind != TokenKind::Percent {
+            return Err(percent_token.error(ParseErrorKind::ExpectedPercent));
+        }
+        if max > 100 {
+            return Err(token.error(ParseErrorKind::DutyOutOfRange));
+        }
+        Ok((Duty::from_percent(min as u8), Duty::from_percent(max as u8)))
+    }
+
+    fn parse_duration(&mut self) -> Result<(u16, u16), ParseError> {
+        let (token, min, max) = self.parse_range()?;
+        let unit_token = self.next()?;
+        let multiplier = match unit_token.kind {
+            TokenKind::Word("ms") => 1,
+            TokenKind::Word("s") => 1_000,
+            _ => return Err(unit_token.error(ParseErrorKind::ExpectedDurationUnit)),
+        };
+        let to_ms = |value: u32| {
+            value
+                .checked_mul(multiplier)
+                .and_then(|duration| u16::try_from(duration).ok())
+                .ok_or(token.error(ParseErrorKind::DurationOutOfRange))
+        };
+        Ok((to_ms(min)?, to_ms(max)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const PARAMETERS: MotionParameters = MotionParameters {
+        min_duty: Duty::from_percent(20),
+        max_duty: Duty::from_percent(100),
+        min_movement_duration: 200,
+        max_movement_duration: 2_000,
+        ramp_profile: RampProfile::Linear,
+        max_ramp_duration: 300,
+        pause_probability_percent: 15,
+        min_pause_duration: 500,
+        max_pause_duration: 3_000,
+    };
+
+    fn steps(source: &str, count: usize) -> Vec<MotionStep> {
+        let mut runner = ScriptRunner::new(Script::parse(source).unwrap());
+        let mut rng = SmallRng::seed_from_u64(0);
+        (0..count)
+            .map(|_| runner.next_step(&mut rng, &PARAMETERS))
+            .collect()
+    }
+
+    fn error(source: &str) -> (u16, u16, ParseErrorKind) {
+        let error = Script::parse(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn move_with_ramp() {
+        assert_eq!(
+            steps("fwd 60% 800ms ramp=s", 2),
+            [MotionStep::Move {
+                direction: MotorDirection::Forward,
+                duty: Duty::from_percent(60),
+                duration_ms: 800,
+                ramp: Ramp {
+                    profile: RampProfile::SCurve,
+                    duration_ms: 300,
+                },
+            }; 2]
+        );
+    }
+
+    #[test]
+    fn default_ramp_follows_parameters_and_is_at_most_half_the_move() {
+        assert_eq!(
+            steps("rev 40% 400ms", 1),
+            [MotionStep::Move {
+                direction: MotorDirection::Reverse,
+                duty: Duty::from_percent(40),
+                duration_ms: 400,
+                ramp: Ramp {
+                    profile: RampProfile::Linear,
+                    duration_ms: 200,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn pause_range_in_seconds() {
+        for step in steps("pause 1-3s", 100) {
+            let MotionStep::Pause { duration_ms } = step else {
+                panic!("expected a pause, got {:?}", step);
+            };
+            assert!((1_000..=3_000).contains(&duration_ms));
+        }
+    }
+
+    #[test]
+    fn repeat_runs_its_body_count_times() {
+        let steps = steps("repeat 4 { fwd 50% 100ms ramp=none }\npause 1s", 10);
+        let kinds: Vec<bool> = steps
+            .iter()
+            .map(|step| matches!(step, MotionStep::Pause { .. }))
+            .collect();
+        assert_eq!(
+            kinds,
+            [false, false, false, false, true, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn nested_repeats_multiply() {
+        let steps = steps("repeat 2 { repeat 3 { fwd 50% 100ms } pause 1s }", 8);
+        let pauses: Vec<usize> = steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| matches!(step, MotionStep::Pause { .. }))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(pauses, [3, 7]);
+    }
+
+    #[test]
+    fn random_picks_every_branch() {
+        let steps = steps(
+            "random { fwd 50% 100ms | rev 50% 100ms | pause 100ms }",
+            200,
+        );
+        let forward = steps.iter().any(|step| {
+            matches!(
+                step,
+                MotionStep::Move {
+                    direction: MotorDirection::Forward,
+                    ..
+                }
+            )
+        });
+        let reverse = steps.iter().any(|step| {
+            matches!(
+                step,
+                MotionStep::Move {
+                    direction: MotorDirection::Reverse,
+                    ..
+                }
+            )
+        });
+        let pause = steps
+            .iter()
+            .any(|step| matches!(step, MotionStep::Pause { .. }));
+        assert!(forward && reverse && pause);
+    }
+
+    #[test]
+    fn module_example_parses() {
+        let source = "# Stalk, then pounce\nrepeat 3 {\n    fwd 25% 1-2s ramp=s\n    pause 500-1500ms\n}\nrandom { rev 90% 300ms ramp=none | fwd 60-80% 400ms }";
+        let steps = steps(source, 7);
+        for step in &steps[..6] {
+            match step {
+                MotionStep::Move { duty, .. } => assert_eq!(*duty, Duty::from_percent(25)),
+                MotionStep::Pause { duration_ms } => {
+                    assert!((500..=1_500).contains(duration_ms))
+                }
+            }
+        }
+        assert!(matches!(
+            steps[6],
+            MotionStep::Move {
+                duration_ms: 300 | 400,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn errors_carry_line_and_column() {
+        assert_eq!(
+            error("fwd 60% 800ms\nspin 50% 1s"),
+            (2, 1, ParseErrorKind::UnknownCommand)
+        );
+        assert_eq!(
+            error("fwd 60 800ms"),
+            (1, 8, ParseErrorKind::ExpectedPercent)
+        );
+        assert_eq!(error("fwd 120% 1s"), (1, 5, ParseErrorKind::DutyOutOfRange));
+        assert_eq!(
+            error("  pause 70s"),
+            (1, 9, ParseErrorKind::DurationOutOfRange)
+        );
+        assert_eq!(error("fwd 50% 3-1s"), (1, 9, ParseErrorKind::InvalidRange));
+        assert_eq!(
+            error("fwd 50% 1s ramp=wobble"),
+            (1, 17, ParseErrorKind::UnknownRampProfile)
+        );
+        assert_eq!(
+            error("fwd 50% 1s\n  @"),
+            (2, 3, ParseErrorKind::UnexpectedCharacter('@'))
+        );
+        assert_eq!(
+            error("repeat 2 {\n  fwd 50% 1s"),
+            (2, 13, ParseErrorKind::UnexpectedEnd)
+        );
+        assert_eq!(
+            error("fwd 50% 1s }"),
+            (1, 12, ParseErrorKind::UnexpectedToken)
+        );
+        assert_eq!(
+            error("# nothing to play"),
+            (1, 1, ParseErrorKind::EmptyScript)
+        );
+    }
+
+    #[test]
+    fn zero_repeat_count_is_rejected() {
+        assert_eq!(
+            error("fwd 50% 1s\nrepeat 0 { rev 50% 1s }"),
+            (2, 8, ParseErrorKind::ZeroRepeatCount)
+        );
+    }
+
+    #[test]
+    fn error_display_names_the_position() {
+        assert_eq!(
+            Script::parse("fwd 60% 800ms\nspin 50% 1s")
+                .unwrap_err()
+                .to_string(),
+            "line 2, column 1: unknown command, expected fwd, rev, pause, repeat or random"
+        );
+    }
+}
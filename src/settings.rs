@@ -0,0 +1,571 @@
+use crate::duty::Duty;
+use crate::flash_record::{self, MAGIC_LEN};
+use crate::schedule::QuietHours;
+use core::fmt;
+
+const MAGIC: [u8; MAGIC_LEN] = *b"SET1";
+// Idle timeout, quiet hours, light sleep, battery monitoring
+pub const SETTINGS_LEN: usize = flash_record::record_len(2 + 5 + 1 + 1);
+
+/// Runtime parameters shared between the knobs, the web API and the main loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub min_motor_duty: Duty,
+    pub max_motor_duty: Duty,
+    pub min_movement_duration: u16, // ms
+    pub max_movement_duration: u16, // ms
+    pub idle_timeout: u16,          // s without activity before going to sleep
+    pub quiet_hours: Option<QuietHours>,
+    pub light_sleep: bool, // Nap in light sleep during pauses, the radio goes unserved meanwhile
+    pub battery_monitoring: bool, // Needs the divider fitted, a floating pin reads as a flat cell
+    pub battery_cutoff: u16, // mV below which the device sleeps to protect the cell
+    pub test: bool,
+    pub arbitration: Arbitration,
+    pub sources: [ParameterSource; Parameter::ALL.len()], // Who last wrote each parameter
+}
+
+/// Parameters that both a knob and the web API can set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Parameter {
+    Speed,
+    Duration,
+}
+
+/// Who gets to set the parameters the knobs control once they can also be set remotely
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arbitration {
+    /// Any knob movement overrides a remote value and vice versa
+    LastWriterWins,
+    /// A remote value holds until its knob is turned past the knob's takeover threshold
+    RemoteUntilKnobMoves,
+    /// Only remote writes are applied
+    KnobsLocked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterSource {
+    Knob,
+    Remote,
+}
+
+/// Hard bounds every setting has to stay within
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SettingsLimits {
+    pub min_motor_duty: Duty,
+    pub max_motor_duty: Duty,
+    pub min_movement_duration: u16, // ms
+    pub max_movement_duration: u16, // ms
+    pub min_idle_timeout: u16,      // s
+    pub max_idle_timeout: u16,      // s
+    pub min_battery_cutoff: u16,    // mV
+    pub max_battery_cutoff: u16,    // mV
+}
+
+/// Changes at least this large interrupt the movement in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrasticChange {
+    pub motor_duty: Duty,
+    pub movement_duration: u16, // ms
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    SpeedRange { min: Duty, max: Duty },
+    DurationRange { min: u16, max: u16 },
+    IdleTimeout { min: u16, max: u16 },
+    BatteryCutoff { min: u16, max: u16 },
+    QuietHours,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::SpeedRange { min, max } => {
+                write!(
+                    f,
+                    "speed range must satisfy {} <= min <= max <= {}",
+                    min, max
+                )
+            }
+            SettingsError::DurationRange { min, max } => write!(
+                f,
+                "duration range must satisfy {} <= min <= max <= {} ms",
+                min, max
+            ),
+            SettingsError::IdleTimeout { min, max } => {
+                write!(f, "idle timeout must be between {} and {} s", min, max)
+            }
+            SettingsError::BatteryCutoff { min, max } => {
+                write!(f, "battery cutoff must be between {} and {} mV", min, max)
+            }
+            SettingsError::QuietHours => {
+                write!(
+                    f,
+                    "quiet hours must start and end at different times of day"
+                )
+            }
+        }
+    }
+}
+
+impl Arbitration {
+    pub fn name(self) -> &'static str {
+        match self {
+            Arbitration::LastWriterWins => "last_writer_wins",
+            Arbitration::RemoteUntilKnobMoves => "remote_until_knob_moves",
+            Arbitration::KnobsLocked => "knobs_locked",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Arbitration::LastWriterWins,
+            Arbitration::RemoteUntilKnobMoves,
+            Arbitration::KnobsLocked,
+        ]
+        .into_iter()
+        .find(|arbitration| arbitration.name() == name)
+    }
+
+    /// `moved_past_threshold` tells whether the knob travelled far enough since it last wrote
+    pub fn knob_may_write(
+        self,
+        current_source: ParameterSource,
+        moved_past_threshold: bool,
+    ) -> bool {
+        match self {
+            Arbitration::LastWriterWins => true,
+            Arbitration::RemoteUntilKnobMoves => {
+                current_source == ParameterSource::Knob || moved_past_threshold
+            }
+            Arbitration::KnobsLocked => false,
+        }
+    }
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 2] = [Parameter::Speed, Parameter::Duration];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Parameter::Speed => "speed",
+            Parameter::Duration => "duration",
+        }
+    }
+}
+
+impl ParameterSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            ParameterSource::Knob => "knob",
+            ParameterSource::Remote => "remote",
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self, limits: &SettingsLimits) -> Result<(), SettingsError> {
+        if !(limits.min_motor_duty <= self.min_motor_duty
+            && self.min_motor_duty <= self.max_motor_duty
+            && self.max_motor_duty <= limits.max_motor_duty)
+        {
+            return Err(SettingsError::SpeedRange {
+                min: limits.min_motor_duty,
+                max: limits.max_motor_duty,
+            });
+        }
+        if !(limits.min_movement_duration <= self.min_movement_duration
+            && self.min_movement_duration <= self.max_movement_duration
+            && self.max_movement_duration <= limits.max_movement_duration)
+        {
+            return Err(SettingsError::DurationRange {
+                min: limits.min_movement_duration,
+                max: limits.max_movement_duration,
+            });
+        }
+        if !(limits.min_idle_timeout..=limits.max_idle_timeout).contains(&self.idle_timeout) {
+            return Err(SettingsError::IdleTimeout {
+                min: limits.min_idle_timeout,
+                max: limits.max_idle_timeout,
+            });
+        }
+        if !(limits.min_battery_cutoff..=limits.max_battery_cutoff).contains(&self.battery_cutoff) {
+            return Err(SettingsError::BatteryCutoff {
+                min: limits.min_battery_cutoff,
+                max: limits.max_battery_cutoff,
+            });
+        }
+        if self
+            .quiet_hours
+            .is_some_and(|quiet_hours| !quiet_hours.is_valid())
+        {
+            return Err(SettingsError::QuietHours);
+        }
+        Ok(())
+    }
+
+    /// Moves the upper speed bound, dragging the lower one along when it would end up above it
+    pub fn set_max_motor_duty(&mut self, duty: Duty) {
+        self.max_motor_duty = duty;
+        self.min_motor_duty = self.min_motor_duty.min(duty);
+    }
+
+    /// Moves the upper duration bound, dragging the lower one along when it would end up above it
+    pub fn set_max_movement_duration(&mut self, duration_ms: u16) {
+        self.max_movement_duration = duration_ms;
+        self.min_movement_duration = self.min_movement_duration.min(duration_ms);
+    }
+
+    pub fn source(&self, parameter: Parameter) -> ParameterSource {
+        self.sources[parameter as usize]
+    }
+
+    /// Applies a knob's output if the arbitration policy lets the knob write, returning whether it did
+    pub fn apply_knob(
+        &mut self,
+        parameter: Parameter,
+        value: u16,
+        moved_past_threshold: bool,
+    ) -> bool {
+        if !self
+            .arbitration
+            .knob_may_write(self.source(parameter), moved_past_threshold)
+        {
+            return false;
+        }
+        match parameter {
+            Parameter::Speed => self.set_max_motor_duty(Duty::from_raw(value)),
+            Parameter::Duration => self.set_max_movement_duration(value),
+        }
+        self.sources[parameter as usize] = ParameterSource::Knob;
+        true
+    }
+
+    /// The settings kept in flash so they survive deep sleep and power loss
+    pub fn to_bytes(&self) -> [u8; SETTINGS_LEN] {
+        flash_record::encode(MAGIC, |payload| {
+            payload[..2].copy_from_slice(&self.idle_timeout.to_le_bytes());
+            if let Some(quiet_hours) = self.quiet_hours {
+                payload[2] = 1;
+                payload[3..5].copy_from_slice(&quiet_hours.start.to_le_bytes());
+                payload[5..7].copy_from_slice(&quiet_hours.end.to_le_bytes());
+            }
+            payload[7] = self.light_sleep as u8;
+            payload[8] = self.battery_monitoring as u8;
+        })
+    }
+
+    /// These settings with the ones kept in flash applied. `None` for erased flash, data written by
+    /// another firmware or a corrupted record
+    pub fn with_stored(self, bytes: &[u8]) -> Option<Self> {
+        let payload = flash_record::decode(MAGIC, bytes, SETTINGS_LEN)?;
+        let quiet_hours = match payload[2] {
+            0 => None,
+            1 => Some(QuietHours {
+                start: u16::from_le_bytes([payload[3], payload[4]]),
+                end: u16::from_le_bytes([payload[5], payload[6]]),
+            }),
+            _ => return None,
+        };
+        Some(Self {
+            idle_timeout: u16::from_le_bytes([payload[0], payload[1]]),
+            quiet_hours,
+            light_sleep: decode_flag(payload[7])?,
+            battery_monitoring: decode_flag(payload[8])?,
+            ..self
+        })
+    }
+
+    pub fn is_drastic_change_from(&self, previous: &Settings, threshold: &DrasticChange) -> bool {
+        let duty_changed = |current: Duty, previous: Duty| {
+            current.raw().abs_diff(previous.raw()) > threshold.motor_duty.raw()
+        };
+        let duration_changed =
+            |current: u16, previous: u16| current.abs_diff(previous) > threshold.movement_duration;
+        duty_changed(self.min_motor_duty, previous.min_motor_duty)
+            || duty_changed(self.max_motor_duty, previous.max_motor_duty)
+            || duration_changed(self.min_movement_duration, previous.min_movement_duration)
+            || duration_changed(self.max_movement_duration, previous.max_movement_duration)
+    }
+}
+
+fn decode_flag(byte: u8) -> Option<bool> {
+    match byte {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SettingsLimits = SettingsLimits {
+        min_motor_duty: Duty::from_percent(10),
+        max_motor_duty: Duty::from_percent(90),
+        min_movement_duration: 100,
+        max_movement_duration: 5_000,
+        min_idle_timeout: 60,
+        max_idle_timeout: 3_600,
+        min_battery_cutoff: 3_000,
+        max_battery_cutoff: 3_600,
+    };
+
+    const SETTINGS: Settings = Settings {
+        min_motor_duty: Duty::from_percent(20),
+        max_motor_duty: Duty::from_percent(60),
+        min_movement_duration: 500,
+        max_movement_duration: 2_000,
+        idle_timeout: 600,
+        quiet_hours: None,
+        light_sleep: false,
+        battery_monitoring: false,
+        battery_cutoff: 3_300,
+        test: false,
+        arbitration: Arbitration::LastWriterWins,
+        sources: [ParameterSource::Knob; Parameter::ALL.len()],
+    };
+
+    const DRASTIC_CHANGE: DrasticChange = DrasticChange {
+        motor_duty: Duty::from_raw(1_000),
+        movement_duration: 300,
+    };
+
+    #[test]
+    fn validate_accepts_the_inclusive_bounds() {
+        assert_eq!(SETTINGS.validate(&LIMITS), Ok(()));
+        let at_lower_bounds = Settings {
+            min_motor_duty: LIMITS.min_motor_duty,
+            max_motor_duty: LIMITS.min_motor_duty,
+            min_movement_duration: LIMITS.min_movement_duration,
+            max_movement_duration: LIMITS.min_movement_duration,
+            idle_timeout: LIMITS.min_idle_timeout,
+            battery_cutoff: LIMITS.min_battery_cutoff,
+            ..SETTINGS
+        };
+        assert_eq!(at_lower_bounds.validate(&LIMITS), Ok(()));
+        let at_upper_bounds = Settings {
+            min_motor_duty: LIMITS.max_motor_duty,
+            max_motor_duty: LIMITS.max_motor_duty,
+            min_movement_duration: LIMITS.max_movement_duration,
+            max_movement_duration: LIMITS.max_movement_duration,
+            idle_timeout: LIMITS.max_idle_timeout,
+            battery_cutoff: LIMITS.max_battery_cutoff,
+            ..SETTINGS
+        };
+        assert_eq!(at_upper_bounds.validate(&LIMITS), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_each_setting_out_of_range() {
+        let speed_error = Err(SettingsError::SpeedRange {
+            min: LIMITS.min_motor_duty,
+            max: LIMITS.max_motor_duty,
+        });
+        let below = Duty::from_raw(LIMITS.min_motor_duty.raw() - 1);
+        let above = Duty::from_raw(LIMITS.max_motor_duty.raw() + 1);
+        for (min_motor_duty, max_motor_duty) in [
+            (below, SETTINGS.max_motor_duty),
+            (SETTINGS.min_motor_duty, above),
+            (SETTINGS.max_motor_duty, SETTINGS.min_motor_duty),
+        ] {
+            let settings = Settings {
+                min_motor_duty,
+                max_motor_duty,
+                ..SETTINGS
+            };
+            assert_eq!(settings.validate(&LIMITS), speed_error);
+        }
+
+        let duration_error = Err(SettingsError::DurationRange {
+            min: LIMITS.min_movement_duration,
+            max: LIMITS.max_movement_duration,
+        });
+        for (min_movement_duration, max_movement_duration) in [
+            (LIMITS.min_movement_duration - 1, 2_000),
+            (500, LIMITS.max_movement_duration + 1),
+            (2_000, 500),
+        ] {
+            let settings = Settings {
+                min_movement_duration,
+                max_movement_duration,
+                ..SETTINGS
+            };
+            assert_eq!(settings.validate(&LIMITS), duration_error);
+        }
+
+        let idle_timeout_error = Err(SettingsError::IdleTimeout {
+            min: LIMITS.min_idle_timeout,
+            max: LIMITS.max_idle_timeout,
+        });
+        for idle_timeout in [LIMITS.min_idle_timeout - 1, LIMITS.max_idle_timeout + 1] {
+            let settings = Settings {
+                idle_timeout,
+                ..SETTINGS
+            };
+            assert_eq!(settings.validate(&LIMITS), idle_timeout_error);
+        }
+
+        let battery_cutoff_error = Err(SettingsError::BatteryCutoff {
+            min: LIMITS.min_battery_cutoff,
+            max: LIMITS.max_battery_cutoff,
+        });
+        for battery_cutoff in [LIMITS.min_battery_cutoff - 1, LIMITS.max_battery_cutoff + 1] {
+            let settings = Settings {
+                battery_cutoff,
+                ..SETTINGS
+            };
+            assert_eq!(settings.validate(&LIMITS), battery_cutoff_error);
+        }
+
+        for quiet_hours in [
+            QuietHours { start: 60, end: 60 },
+            QuietHours {
+                start: 24 * 60,
+                end: 60,
+            },
+        ] {
+            let settings = Settings {
+                quiet_hours: Some(quiet_hours),
+                ..SETTINGS
+            };
+            assert_eq!(settings.validate(&LIMITS), Err(SettingsError::QuietHours));
+        }
+    }
+
+    #[test]
+    fn lowering_a_max_pulls_the_min_down_with_it() {
+        let mut settings = SETTINGS;
+        settings.set_max_motor_duty(Duty::from_percent(40));
+        assert_eq!(settings.min_motor_duty, Duty::from_percent(20));
+        settings.set_max_motor_duty(Duty::from_percent(15));
+        assert_eq!(settings.min_motor_duty, Duty::from_percent(15));
+        assert_eq!(settings.max_motor_duty, Duty::from_percent(15));
+
+        settings.set_max_movement_duration(1_000);
+        assert_eq!(settings.min_movement_duration, 500);
+        settings.set_max_movement_duration(200);
+        assert_eq!(settings.min_movement_duration, 200);
+        assert_eq!(settings.max_movement_duration, 200);
+
+        // Raising the max again leaves the min where it was dragged to
+        settings.set_max_movement_duration(2_000);
+        assert_eq!(settings.min_movement_duration, 200);
+    }
+
+    #[test]
+    fn drastic_change_needs_to_exceed_the_threshold() {
+        let duty_by = |delta: u16| Settings {
+            max_motor_duty: Duty::from_raw(SETTINGS.max_motor_duty.raw() + delta),
+            ..SETTINGS
+        };
+        assert!(!duty_by(1_000).is_drastic_change_from(&SETTINGS, &DRASTIC_CHANGE));
+        assert!(duty_by(1_001).is_drastic_change_from(&SETTINGS, &DRASTIC_CHANGE));
+        // Either direction counts
+        assert!(SETTINGS.is_drastic_change_from(&duty_by(1_001), &DRASTIC_CHANGE));
+
+        let duration_by = |delta: u16| Settings {
+            min_movement_duration: SETTINGS.min_movement_duration + delta,
+            ..SETTINGS
+        };
+        assert!(!duration_by(300).is_drastic_change_from(&SETTINGS, &DRASTIC_CHANGE));
+        assert!(duration_by(301).is_drastic_change_from(&SETTINGS, &DRASTIC_CHANGE));
+    }
+
+    #[test]
+    fn stored_settings_round_trip_onto_the_defaults() {
+        let changed = Settings {
+            idle_timeout: 1_800,
+            quiet_hours: Some(QuietHours {
+                start: 22 * 60,
+                end: 7 * 60,
+            }),
+            light_sleep: true,
+            battery_monitoring: true,
+            ..SETTINGS
+        };
+        let bytes = changed.to_bytes();
+        assert_eq!(SETTINGS.with_stored(&bytes), Some(changed));
+        let turned_off = Settings {
+            quiet_hours: None,
+            ..changed
+        };
+        assert_eq!(
+            changed.with_stored(&turned_off.to_bytes()),
+            Some(turned_off)
+        );
+
+        let mut corrupted = bytes;
+        corrupted[MAGIC_LEN] ^= 1;
+        assert_eq!(SETTINGS.with_stored(&corrupted), None);
+        assert_eq!(SETTINGS.with_stored(&[0xFF; SETTINGS_LEN]), None);
+    }
+
+    #[test]
+    fn last_writer_wins_always_lets_the_knob_write() {
+        let mut settings = Settings {
+            sources: [ParameterSource::Remote; Parameter::ALL.len()],
+            ..SETTINGS
+        };
+        assert!(settings.apply_knob(Parameter::Speed, Duty::from_percent(50).raw(), false));
+        assert_eq!(settings.max_motor_duty, Duty::from_percent(50));
+        assert_eq!(settings.source(Parameter::Speed), ParameterSource::Knob);
+        assert!(settings.apply_knob(Parameter::Duration, 1_500, false));
+        assert_eq!(settings.max_movement_duration, 1_500);
+    }
+
+    #[test]
+    fn remote_value_holds_until_the_knob_moves_past_its_threshold() {
+        let mut settings = Settings {
+            arbitration: Arbitration::RemoteUntilKnobMoves,
+            sources: [ParameterSource::Remote, ParameterSource::Knob],
+            ..SETTINGS
+        };
+        assert!(!settings.apply_knob(Parameter::Speed, Duty::from_percent(50).raw(), false));
+        assert_eq!(settings.max_motor_duty, SETTINGS.max_motor_duty);
+        assert_eq!(settings.source(Parameter::Speed), ParameterSource::Remote);
+
+        assert!(settings.apply_knob(Parameter::Speed, Duty::from_percent(50).raw(), true));
+        assert_eq!(settings.max_motor_duty, Duty::from_percent(50));
+        assert_eq!(settings.source(Parameter::Speed), ParameterSource::Knob);
+
+        // Once the knob owns the parameter, small movements go through
+        assert!(settings.apply_knob(Parameter::Speed, Duty::from_percent(52).raw(), false));
+        assert!(settings.apply_knob(Parameter::Duration, 1_500, false));
+    }
+
+    #[test]
+    fn locked_knobs_never_write() {
+        let mut settings = Settings {
+            arbitration: Arbitration::KnobsLocked,
+            ..SETTINGS
+        };
+        for parameter in Parameter::ALL {
+            assert!(!settings.apply_knob(parameter, 1_000, true));
+        }
+        assert_eq!(
+            settings,
+            Settings {
+                arbitration: Arbitration::KnobsLocked,
+                ..SETTINGS
+            }
+        );
+    }
+
+    #[test]
+    fn arbitration_names_round_trip() {
+        for arbitration in [
+            Arbitration::LastWriterWins,
+            Arbitration::RemoteUntilKnobMoves,
+            Arbitration::KnobsLocked,
+        ] {
+            assert_eq!(
+                Arbitration::from_name(arbitration.name()),
+                Some(arbitration)
+            );
+        }
+        assert_eq!(Arbitration::from_name("knobs"), None);
+    }
+}
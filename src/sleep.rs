@@ -0,0 +1,39 @@
+use esp_hal::rtc_cntl::SleepSource;
+
+/// Which sources may bring the chip back from deep sleep
+#[derive(Debug, Clone, Copy)]
+pub struct WakeSources {
+    pub button: bool,
+    pub timer: Option<u32>, // s after falling asleep
+}
+
+/// Why the chip is running, as reported after boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeReason {
+    PowerOn, // Not woken from deep sleep, e.g. switched on or reset
+    Button,
+    Timer,
+    Other,
+}
+
+impl WakeReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            WakeReason::PowerOn => "power_on",
+            WakeReason::Button => "button",
+            WakeReason::Timer => "timer",
+            WakeReason::Other => "other",
+        }
+    }
+}
+
+impl From<SleepSource> for WakeReason {
+    fn from(source: SleepSource) -> Self {
+        match source {
+            SleepSource::Undefined => WakeReason::PowerOn,
+            SleepSource::Gpio => WakeReason::Button,
+            SleepSource::Timer => WakeReason::Timer,
+            _ => WakeReason::Other,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use core::fmt;
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_LEN: usize = 48;
+const NTP_UNIX_EPOCH_OFFSET: u64 = 2_208_988_800; // s from 1900-01-01 to 1970-01-01
+const NTP_ERA_LEN: u64 = 1 << 32; // s, the seconds field wraps in 2036
+const RTC_WALL_CLOCK_MAGIC: u64 = u64::from_le_bytes(*b"WALLCLK1");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SntpError {
+    TooShort,
+    NotAServerReply,
+    Unsynchronized, // The server has no time to give, e.g. a kiss-o'-death reply
+}
+
+impl fmt::Display for SntpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SntpError::TooShort => write!(f, "the reply is shorter than {} bytes", PACKET_LEN),
+            SntpError::NotAServerReply => write!(f, "the reply does not come from a server"),
+            SntpError::Unsynchronized => write!(f, "the server is not synchronized"),
+        }
+    }
+}
+
+/// Client request asking for the server's transmit time
+pub fn request() -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    packet[0] = 0b00_100_011; // No leap second warning, version 4, client mode
+    packet
+}
+
+/// Unix time in ms at which the server sent the reply
+pub fn parse_reply(reply: &[u8]) -> Result<u64, SntpError> {
+    let reply = reply.get(..PACKET_LEN).ok_or(SntpError::TooShort)?;
+    if reply[0] & 0b111 != 4 {
+        return Err(SntpError::NotAServerReply);
+    }
+    if reply[0] >> 6 == 3 || reply[1] == 0 {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    let seconds = u32::from_be_bytes([reply[40], reply[41], reply[42], reply[43]]) as u64;
+    let fraction = u32::from_be_bytes([reply[44], reply[45], reply[46], reply[47]]) as u64;
+    // Timestamps before the Unix epoch can only belong to the era that starts in 2036
+    let unix_seconds = if seconds >= NTP_UNIX_EPOCH_OFFSET {
+        seconds - NTP_UNIX_EPOCH_OFFSET
+    } else {
+        seconds + NTP_ERA_LEN - NTP_UNIX_EPOCH_OFFSET
+    };
+    Ok(unix_seconds * 1000 + ((fraction * 1000) >> 32))
+}
+
+/// The wall clock as words to keep in memory that survives deep sleep: a marker and the offset of
+/// Unix time from the RTC timer, which keeps counting while the chip sleeps
+pub fn rtc_wall_clock(unix_time_ms: u64, rtc_time_ms: u64) -> [u64; 2] {
+    [RTC_WALL_CLOCK_MAGIC, unix_time_ms.wrapping_sub(rtc_time_ms)]
+}
+
+/// Unix time in ms from words written by `rtc_wall_clock`, `None` for anything else such as the
+/// random contents of RTC memory after power-on
+pub fn restore_wall_clock(words: [u64; 2], rtc_time_ms: u64) -> Option<u64> {
+    (words[0] == RTC_WALL_CLOCK_MAGIC).then(|| words[1].wrapping_add(rtc_time_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(seconds: u32, fraction: u32) -> [u8; PACKET_LEN] {
+        let mut reply = [0; PACKET_LEN];
+        reply[0] = 0b00_100_100; // Version 4, server mode
+        reply[1] = 2; // Stratum
+        reply[40..44].copy_from_slice(&seconds.to_be_bytes());
+        reply[44..].copy_from_slice(&fraction.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn parses_the_transmit_time() {
+        let seconds = (NTP_UNIX_EPOCH_OFFSET + 1_700_000_000) as u32;
+        assert_eq!(parse_reply(&reply(seconds, 1 << 31)), Ok(1_700_000_000_500));
+        assert_eq!(
+            parse_reply(&reply(0, 0)),
+            Ok((NTP_ERA_LEN - NTP_UNIX_EPOCH_OFFSET) * 1000)
+        );
+    }
+
+    #[test]
+    fn rejects_replies_without_a_time() {
+        assert_eq!(parse_reply(&[0; 47]), Err(SntpError::TooShort));
+        assert_eq!(parse_reply(&request()), Err(SntpError::NotAServerReply));
+        let mut kiss_of_death = reply(0, 0);
+        kiss_of_death[1] = 0;
+        assert_eq!(parse_reply(&kiss_of_death), Err(SntpError::Unsynchronized));
+    }
+
+    #[test]
+    fn wall_clock_keeps_counting_through_deep_sleep() {
+        let words = rtc_wall_clock(1_700_000_000_000, 5_000);
+        let slept = 8 * 60 * 60 * 1000; // ms
+        assert_eq!(
+            restore_wall_clock(words, 5_000 + slept),
+            Some(1_700_000_000_000 + slept)
+        );
+        assert_eq!(restore_wall_clock([0, words[1]], 5_000), None);
+    }
+}